@@ -3,33 +3,43 @@
 use std::{
     collections::{HashMap, VecDeque},
     fs,
-    io::{Read, Result, Write},
-    path::{Path, PathBuf},
+    io::{IsTerminal, Result, Write},
+    path::PathBuf,
+    time::UNIX_EPOCH,
 };
 
 use copypasta::ClipboardProvider;
 use crossterm::{
     cursor, execute, queue,
-    style::{Color, SetForegroundColor},
+    style::{Attribute, Color, SetAttribute, SetForegroundColor},
     terminal,
 };
 
 use crate::{
     absolute_pathbuf_to_string, binaryfinder,
-    utils::{THEMES, Theme},
+    config::{self, Config},
+    filters, find,
+    frecency::{self, VisitedDirectory},
+    highlight_input,
+    history::HistoryEntry,
+    prompt::{Placeholder, PromptKind, PromptTemplate},
+    records::{Field, OutputFormat, Records, take_format_flags},
+    theme::{self, Theme},
+    transfer::{Transfer, TransferOptions},
+    trash,
+    utils::wildcard_match,
+    viewer,
 };
 /// Matches a string pattern with wildcards against a set of entries.
 ///
 /// I.e. with the entries `["hello world", "cool world", "wahoo"]`, and the pattern `* world`, would yield `["hello world", "cool world"]`
 fn match_pattern(entries: &[String], pattern: &str) -> Vec<String> {
-    if let Some(split) = pattern.split_once("*") {
-        let (startswith, endswith) = split;
-        let g = entries
+    if pattern.contains(['*', '?']) {
+        entries
             .iter()
-            .filter(|f| f.starts_with(startswith) && f.ends_with(endswith))
+            .filter(|f| wildcard_match(pattern, f))
             .map(|f| f.to_string())
-            .collect();
-        g
+            .collect()
     } else {
         for item in entries {
             if *item == pattern {
@@ -102,7 +112,7 @@ fn into_columns(input: String) -> Result<String> {
     let amount_of_items = input.split('\n').count();
 
     let columns = (width / longest_line_length).max(1);
-    let rows = (amount_of_items / columns).max(1);
+    let rows = amount_of_items.div_ceil(columns).max(1);
 
     let mut new = vec![String::new(); rows];
     for (index, line) in input.split('\n').enumerate() {
@@ -124,8 +134,135 @@ fn column(context: &mut CommandContext) -> Result<CommandResult> {
     Ok(CommandResult::Lovely)
 }
 
+/// What to sort the items listed by `ls` by
+enum LsSort {
+    Name,
+    Size,
+    Time,
+    Extension,
+}
+
+/// An item listed by `ls`
+struct LsItem {
+    name: String,
+    is_dir: bool,
+    /// Where the item points to, if it is a symlink
+    symlink_target: Option<PathBuf>,
+    metadata: Option<fs::Metadata>,
+}
+
+impl LsItem {
+    fn size(&self) -> u64 {
+        self.metadata.as_ref().map_or(0, |metadata| metadata.len())
+    }
+    fn modified(&self) -> i64 {
+        self.metadata
+            .as_ref()
+            .and_then(|metadata| metadata.modified().ok())
+            .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+            .map_or(0, |time| time.as_secs() as i64)
+    }
+    fn extension(&self) -> &str {
+        match self.name.rsplit_once('.') {
+            Some((stem, extension)) if !stem.is_empty() => extension,
+            _ => "",
+        }
+    }
+    fn is_hidden(&self) -> bool {
+        #[cfg(windows)]
+        {
+            use std::os::windows::fs::MetadataExt;
+            const FILE_ATTRIBUTE_HIDDEN: u32 = 0x2;
+            if let Some(metadata) = &self.metadata
+                && metadata.file_attributes() & FILE_ATTRIBUTE_HIDDEN != 0
+            {
+                return true;
+            }
+        }
+        self.name.starts_with('.')
+    }
+    /// Permissions as a string, like `drwxr-xr-x`. On windows, only the readonly flag is known, so will look like `-rw`
+    fn permissions(&self) -> String {
+        let kind = if self.symlink_target.is_some() {
+            'l'
+        } else if self.is_dir {
+            'd'
+        } else {
+            '-'
+        };
+        let Some(metadata) = &self.metadata else {
+            return format!("{}?????????", kind);
+        };
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = metadata.permissions().mode();
+            let mut text = String::from(kind);
+            for (bit, char) in [
+                (0o400, 'r'),
+                (0o200, 'w'),
+                (0o100, 'x'),
+                (0o040, 'r'),
+                (0o020, 'w'),
+                (0o010, 'x'),
+                (0o004, 'r'),
+                (0o002, 'w'),
+                (0o001, 'x'),
+            ] {
+                text.push(if mode & bit != 0 { char } else { '-' });
+            }
+            text
+        }
+        #[cfg(not(unix))]
+        {
+            let write = if metadata.permissions().readonly() {
+                '-'
+            } else {
+                'w'
+            };
+            format!("{}r{}", kind, write)
+        }
+    }
+}
+
 fn ls(context: &mut CommandContext) -> Result<CommandResult> {
-    let path: PathBuf = context.args.front().unwrap_or(&".").into();
+    let mut path: PathBuf = ".".into();
+    let mut long = false;
+    let mut show_hidden = false;
+    let mut reverse = false;
+    let mut sort = LsSort::Name;
+
+    let (format, args) = take_format_flags(context);
+    for arg in args {
+        if let Some(value) = arg.strip_prefix("--sort=") {
+            sort = match value {
+                "name" => LsSort::Name,
+                "size" => LsSort::Size,
+                "time" => LsSort::Time,
+                "ext" | "extension" => LsSort::Extension,
+                _ => Err(std::io::Error::other(
+                    "Sort must be one of 'name', 'size', 'time' or 'ext'",
+                ))?,
+            };
+        } else if let Some(flags) = arg.strip_prefix('-')
+            && !flags.is_empty()
+        {
+            // flags can be combined, like -la
+            for flag in flags.chars() {
+                match flag {
+                    'l' => long = true,
+                    'a' => show_hidden = true,
+                    'r' => reverse = true,
+                    'S' => sort = LsSort::Size,
+                    't' => sort = LsSort::Time,
+                    'X' => sort = LsSort::Extension,
+                    _ => Err(std::io::Error::other(format!("Unknown flag '-{}'", flag)))?,
+                }
+            }
+        } else {
+            path = arg.into();
+        }
+    }
 
     if !path.exists() {
         Err(std::io::Error::other("Directory doesn't exist"))?
@@ -133,29 +270,111 @@ fn ls(context: &mut CommandContext) -> Result<CommandResult> {
     if path.is_file() {
         Err(std::io::Error::other("Path is a file"))?
     }
-    let items = fs::read_dir(path)?;
 
-    let mut dirs = vec![];
-    let mut files = vec![];
-    for item in items.flatten() {
-        let name = item.file_name().to_string_lossy().to_string();
-        if item.file_type()?.is_file() {
-            files.push(name);
+    let mut items = Vec::new();
+    for item in fs::read_dir(path)?.flatten() {
+        let file_type = item.file_type()?;
+        let symlink_target = if file_type.is_symlink() {
+            fs::read_link(item.path()).ok()
         } else {
-            dirs.push(name)
-        }
+            None
+        };
+        // follow symlinks, to know whether they point to a directory
+        let metadata = fs::metadata(item.path()).or_else(|_| item.metadata()).ok();
+        let is_dir = metadata.as_ref().is_some_and(|metadata| metadata.is_dir());
+        items.push(LsItem {
+            name: item.file_name().to_string_lossy().to_string(),
+            is_dir,
+            symlink_target,
+            metadata,
+        });
     }
-    for dir in dirs {
-        queue!(
-            context.stdout,
-            SetForegroundColor(context.theme.primary_color)
-        )?;
-        writeln!(context.stdout, "{}", dir)?;
+    if !show_hidden {
+        items.retain(|item| !item.is_hidden());
     }
-    for dir in files {
+
+    items.sort_by(|a, b| {
+        let ordering = match sort {
+            LsSort::Name => a.name.to_lowercase().cmp(&b.name.to_lowercase()),
+            // biggest and newest first
+            LsSort::Size => b.size().cmp(&a.size()),
+            LsSort::Time => b.modified().cmp(&a.modified()),
+            LsSort::Extension => a.extension().cmp(b.extension()),
+        }
+        .then_with(|| a.name.cmp(&b.name));
+        if reverse {
+            ordering.reverse()
+        } else {
+            ordering
+        }
+    });
+    // directories are always listed first
+    items.sort_by_key(|item| !item.is_dir);
+
+    let color_of = |item: &LsItem| {
+        if item.symlink_target.is_some() {
+            context.theme.secondary_color
+        } else if item.is_dir {
+            context.theme.primary_color
+        } else {
+            Color::Reset
+        }
+    };
+
+    // when piped or redirected, just write one name per line, unless long format is requested
+    if format == OutputFormat::Table && !long && !context.output_is_terminal {
+        for item in &items {
+            writeln!(context.stdout, "{}", item.name)?;
+        }
+        return Ok(CommandResult::Lovely);
+    }
+    // on a terminal, just show the names in columns, unless long format is requested
+    if format == OutputFormat::Table && !long {
+        let mut output = Vec::new();
+        for item in &items {
+            queue!(output, SetForegroundColor(color_of(item)))?;
+            write!(output, "{}", item.name)?;
+            // seperate items by newlines, so they can be broken into columns
+            if !std::ptr::eq(item, items.last().unwrap()) {
+                writeln!(output)?;
+            }
+        }
+        if !items.is_empty() {
+            let columns = into_columns(String::from_utf8_lossy(&output).to_string())?;
+            writeln!(context.stdout, "{}", columns)?;
+        }
         queue!(context.stdout, SetForegroundColor(Color::Reset))?;
-        writeln!(context.stdout, "{}", dir)?;
+        return Ok(CommandResult::Lovely);
     }
+
+    let mut records = Records::new(&["type", "permissions", "size", "modified", "name", "target"]);
+    for item in &items {
+        let kind = if item.symlink_target.is_some() {
+            "link"
+        } else if item.is_dir {
+            "dir"
+        } else {
+            "file"
+        };
+        let size = if item.is_dir {
+            Field::empty()
+        } else {
+            Field::size(item.size())
+        };
+        let target = match &item.symlink_target {
+            Some(target) => Field::text(target.to_string_lossy()),
+            None => Field::empty(),
+        };
+        records.push(vec![
+            Field::text(kind).colored(Color::DarkGrey),
+            Field::text(item.permissions()).colored(Color::DarkGrey),
+            size.colored(Color::DarkGrey),
+            Field::time(item.modified()).colored(Color::DarkGrey),
+            Field::text(&item.name).colored(color_of(item)),
+            target,
+        ]);
+    }
+    records.write(context, format)?;
     Ok(CommandResult::Lovely)
 }
 fn export(context: &mut CommandContext) -> Result<CommandResult> {
@@ -169,18 +388,127 @@ fn export(context: &mut CommandContext) -> Result<CommandResult> {
     Ok(CommandResult::SetEnvVar(key.to_string(), value.to_string()))
 }
 
-fn cd(context: &mut CommandContext) -> Result<CommandResult> {
-    let path = context.args.front();
-    if let Some(path) = path {
-        let path = PathBuf::from(path);
+/// Directories to look for relative `cd` targets in, from the CDPATH variable (shoe's own, or the process')
+fn cd_search_path(context: &CommandContext) -> Vec<PathBuf> {
+    let value = match context.enviroment_variables.get("CDPATH") {
+        Some(value) => value.into(),
+        None => std::env::var_os("CDPATH").unwrap_or_default(),
+    };
+    std::env::split_paths(&value)
+        .filter(|dir| !dir.as_os_str().is_empty())
+        .collect()
+}
+
+/// Find the directory to change to for `cd` and `pushd`.
+///
+/// No target means the home directory, and `-` means the previous directory.
+/// Relative targets that don't exist in the cwd are looked for in the CDPATH dirs.
+/// If the target is a file, asks whether to go to its parent instead. Returns None if not.
+fn resolve_cd_target(context: &CommandContext, target: Option<&str>) -> Result<Option<PathBuf>> {
+    let mut path = match target {
+        None => PathBuf::from(shellexpand::tilde("~").to_string()),
+        Some("-") => match context.previous_directory {
+            Some(previous) => previous.clone(),
+            None => return Err(std::io::Error::other("No previous directory")),
+        },
+        Some(target) => PathBuf::from(target),
+    };
+
+    let is_plain_relative = path.is_relative()
+        && !matches!(
+            path.components().next(),
+            Some(std::path::Component::CurDir | std::path::Component::ParentDir)
+        );
+    if !path.exists()
+        && is_plain_relative
+        && let Some(found) = cd_search_path(context)
+            .into_iter()
+            .map(|dir| dir.join(&path))
+            .find(|candidate| candidate.is_dir())
+    {
+        path = found;
+    }
 
-        if !path.exists() {
-            Err(std::io::Error::other("Directory doesn't exist"))?
+    if !path.exists() {
+        Err(std::io::Error::other("Directory doesn't exist"))?
+    }
+    if path.is_file() {
+        let question = format!(
+            "'{}' is a file. Go to its directory instead?",
+            path.to_string_lossy()
+        );
+        if !confirm(&question)? {
+            return Ok(None);
         }
-        if path.is_file() {
-            Err(std::io::Error::other("Path is a file"))?
+        path = std::path::absolute(&path)?
+            .parent()
+            .map(|parent| parent.to_path_buf())
+            .unwrap_or(path);
+    }
+    Ok(Some(path))
+}
+fn cd(context: &mut CommandContext) -> Result<CommandResult> {
+    match resolve_cd_target(context, context.args.front().copied())? {
+        Some(path) => Ok(CommandResult::ChangeDirectory(path)),
+        None => Ok(CommandResult::Lovely),
+    }
+}
+fn pushd(context: &mut CommandContext) -> Result<CommandResult> {
+    // without a dir, swap the current dir with the one on top of the stack
+    let Some(target) = context.args.front().copied() else {
+        if context.directory_stack.is_empty() {
+            return Err(std::io::Error::other("Directory stack is empty"));
         }
-        std::env::set_current_dir(path)?;
+        return Ok(CommandResult::SwapDirectory);
+    };
+    match resolve_cd_target(context, Some(target))? {
+        Some(path) => Ok(CommandResult::PushDirectory(path)),
+        None => Ok(CommandResult::Lovely),
+    }
+}
+fn popd(context: &mut CommandContext) -> Result<CommandResult> {
+    if context.directory_stack.is_empty() {
+        return Err(std::io::Error::other("Directory stack is empty"));
+    }
+    Ok(CommandResult::PopDirectory)
+}
+/// Jump to the most frecent visited directory matching the args. Without args, list the visited directories
+fn z(context: &mut CommandContext) -> Result<CommandResult> {
+    let (format, fragments) = take_format_flags(context);
+    let fragments: Vec<&str> = fragments.into_iter().collect();
+    if fragments.is_empty() {
+        let now = frecency::now();
+        let mut directories: Vec<&VisitedDirectory> = context.visited_directories.iter().collect();
+        directories.sort_by(|a, b| b.score(now).total_cmp(&a.score(now)));
+
+        let mut records = Records::new(&["score", "visits", "last visit", "path"]);
+        for directory in directories {
+            records.push(vec![
+                Field::integer(directory.score(now).round() as i64),
+                Field::integer(directory.visits.round() as i64),
+                Field::time(directory.last_visit as i64),
+                Field::text(&directory.path).colored(context.theme.primary_color),
+            ]);
+        }
+        records.write(context, format)?;
+        return Ok(CommandResult::Lovely);
+    }
+    match frecency::find_matches(context.visited_directories, &fragments).first() {
+        Some(best) => Ok(CommandResult::ChangeDirectory(PathBuf::from(&best.path))),
+        None => Err(std::io::Error::other("No matching directory")),
+    }
+}
+/// List the directory stack, starting with the cwd and then the most recently pushed
+fn dirs(context: &mut CommandContext) -> Result<CommandResult> {
+    let current = std::env::current_dir()?;
+    let directories = std::iter::once(&current).chain(context.directory_stack.iter().rev());
+    for (index, directory) in directories.enumerate() {
+        writeln!(
+            context.stdout,
+            "{} {}",
+            index,
+            absolute_pathbuf_to_string(directory)
+        )?;
     }
     Ok(CommandResult::Lovely)
 }
@@ -281,34 +609,178 @@ fn cls(context: &mut CommandContext) -> Result<CommandResult> {
     )?;
     Ok(CommandResult::Lovely)
 }
-fn cat(context: &mut CommandContext) -> Result<CommandResult> {
-    let path = context.args.front();
-    match path {
-        Some(path) => {
-            let mut file = fs::File::open(path)?;
-            let mut buf = String::new();
-            file.read_to_string(&mut buf)?;
+/// How much of the start of a file is checked when guessing if it is binary
+const BINARY_CHECK_LEN: usize = 8192;
 
-            queue!(context.stdout, SetForegroundColor(Color::Reset))?;
-            writeln!(context.stdout, "{}", buf)?;
+/// Guess whether data is binary (and not text), by checking for null bytes or invalid utf-8 near the start
+fn looks_binary(data: &[u8]) -> bool {
+    let sample = &data[..data.len().min(BINARY_CHECK_LEN)];
+    if sample.contains(&0) {
+        return true;
+    }
+    match std::str::from_utf8(sample) {
+        Ok(_) => false,
+        // a character cut off at the end of the sample doesn't count
+        Err(error) => error.error_len().is_some(),
+    }
+}
+
+fn cat(context: &mut CommandContext) -> Result<CommandResult> {
+    let mut number_lines = false;
+    let mut paths = Vec::new();
+    for arg in context.args {
+        match *arg {
+            "-n" | "--number" => number_lines = true,
+            _ => paths.push(*arg),
         }
-        None => {
-            writeln!(context.stdout, "meow")?;
+    }
+    context.raw_output = true;
+
+    // line numbers continue across files, like a single concatenated file
+    let mut numbering = number_lines.then_some(LineNumbering {
+        next: 1,
+        at_line_start: true,
+    });
+
+    // with no files, read stdin
+    if paths.is_empty() {
+        let data = std::mem::take(&mut context.stdin);
+        write_cat_source(context, "stdin", &data, &mut numbering)?;
+        return Ok(CommandResult::Lovely);
+    }
+
+    // one file at a time, so files that can't be read don't stop the others from being printed
+    let mut failed = 0;
+    for path in paths {
+        match fs::read(path) {
+            Ok(data) => write_cat_source(context, path, &data, &mut numbering)?,
+            Err(error) => {
+                failed += 1;
+                let mut stderr = std::io::stderr();
+                queue!(stderr, SetForegroundColor(context.theme.err_color))?;
+                writeln!(stderr, "Couldn't read '{}': {}", path, error)?;
+                queue!(stderr, SetForegroundColor(Color::Reset))?;
+            }
         }
     }
+    if failed > 0 {
+        let message = format!(
+            "Couldn't read {} file{}",
+            failed,
+            if failed == 1 { "" } else { "s" }
+        );
+        Err(std::io::Error::other(message))?;
+    }
 
     Ok(CommandResult::Lovely)
 }
+struct LineNumbering {
+    next: usize,
+    at_line_start: bool,
+}
+/// Write the data of one of cat's files, asking first if it looks binary
+fn write_cat_source(
+    context: &mut CommandContext,
+    name: &str,
+    data: &[u8],
+    numbering: &mut Option<LineNumbering>,
+) -> Result<()> {
+    if context.output_is_terminal
+        && looks_binary(data)
+        && !confirm(&format!(
+            "'{}' looks like a binary file. Print it anyway?",
+            name
+        ))?
+    {
+        return Ok(());
+    }
+    let Some(numbering) = numbering else {
+        return context.stdout.write_all(data);
+    };
+    for line in data.split_inclusive(|byte| *byte == b'\n') {
+        if numbering.at_line_start {
+            write!(context.stdout, "{:>6}  ", numbering.next)?;
+            numbering.next += 1;
+        }
+        context.stdout.write_all(line)?;
+        numbering.at_line_start = line.ends_with(b"\n");
+    }
+    Ok(())
+}
+fn view(context: &mut CommandContext) -> Result<CommandResult> {
+    let (name, data) = match context.args.front() {
+        Some(path) => (path.to_string(), fs::read(path)?),
+        None => (String::from("stdin"), std::mem::take(&mut context.stdin)),
+    };
+    if looks_binary(&data) {
+        let message = format!("'{}' looks like a binary file", name);
+        return Err(std::io::Error::other(message));
+    }
+    // when piped or redirected, there's nothing to page, so just pass the text through
+    if !context.output_is_terminal {
+        context.raw_output = true;
+        context.stdout.write_all(&data)?;
+        return Ok(CommandResult::Lovely);
+    }
+    viewer::view(&name, &String::from_utf8_lossy(&data), context.theme)?;
+    Ok(CommandResult::Lovely)
+}
+/// Set the prompt template. Without args, go back to the default prompt
+fn prompt(context: &mut CommandContext) -> Result<CommandResult> {
+    let mut args = context.args.iter().copied().peekable();
+    let kind = match args.peek() {
+        Some(&"--right") => PromptKind::Right,
+        Some(&"--transient") => PromptKind::Transient,
+        _ => PromptKind::Main,
+    };
+    if !matches!(kind, PromptKind::Main) {
+        args.next();
+    }
+    let template = args.collect::<Vec<&str>>().join(" ");
+    // no template means the default prompt, or no right/transient prompt
+    if template.is_empty() {
+        return Ok(CommandResult::SetPrompt(kind, None));
+    }
+    let template = PromptTemplate::parse_kind(&template, kind)?;
+    Ok(CommandResult::SetPrompt(kind, Some(template)))
+}
 fn help(context: &mut CommandContext) -> Result<CommandResult> {
     writeln!(context.stdout, "{}", include_str!("help.txt"))?;
     Ok(CommandResult::Lovely)
 }
-fn cp(context: &mut CommandContext) -> Result<CommandResult> {
-    if context.args.len() != 2 {
-        Err(std::io::Error::other("Usage: 'cp <source> <dest>'"))?;
+/// Parse the args of `cp` and `mv` into options, source pattern and destination
+fn parse_transfer_args<'a>(
+    context: &CommandContext<'a>,
+    usage: &str,
+) -> Result<(TransferOptions, &'a str, &'a str)> {
+    let mut options = TransferOptions::default();
+    let mut paths = Vec::new();
+    for arg in context.args {
+        if let Some(flags) = arg.strip_prefix('-')
+            && !flags.is_empty()
+        {
+            for flag in flags.chars() {
+                match flag {
+                    'n' => options.no_clobber = true,
+                    'u' => options.update = true,
+                    'p' => options.preserve = true,
+                    _ => Err(std::io::Error::other(format!("Unknown flag '-{}'", flag)))?,
+                }
+            }
+        } else {
+            paths.push(*arg);
+        }
     }
-    let dest_pathbuf = PathBuf::from(context.args[1]);
-    let (matches, source_parent) = match_file_pattern(context.args[0])?;
+    if paths.len() != 2 {
+        Err(std::io::Error::other(usage.to_string()))?;
+    }
+    Ok((options, paths[0], paths[1]))
+}
+
+/// Get pairs of source and destination paths for `cp` and `mv`
+fn transfer_pairs(source: &str, dest: &str) -> Result<Vec<(PathBuf, PathBuf)>> {
+    let dest_pathbuf = PathBuf::from(dest);
+    let (matches, source_parent) = match_file_pattern(source)?;
     let more_than_1_match = matches.len() > 1;
 
     // if more than 1 match, validate that the output dest is a directory, and not direct file path
@@ -322,61 +794,165 @@ fn cp(context: &mut CommandContext) -> Result<CommandResult> {
         Err(std::io::Error::other("Source item(s) not found."))?;
     }
 
+    let mut pairs = Vec::new();
     for name in matches {
         let mut dest_pathbuf = dest_pathbuf.clone();
         let source = source_parent.join(&name);
 
-        let source_is_file = source.is_file();
-
         // if source is a file, and destination is a directory (without filename), append the source filename to the destination path
-        if more_than_1_match || (source_is_file && dest_pathbuf.is_dir()) {
+        if more_than_1_match || (!source.is_dir() && dest_pathbuf.is_dir()) {
             dest_pathbuf.push(&name);
         }
+        pairs.push((source, dest_pathbuf));
+    }
+    Ok(pairs)
+}
 
-        if source_is_file {
-            std::fs::copy(&source, dest_pathbuf)?;
-        } else {
-            copy_dir(&source, dest_pathbuf)?;
+fn cp(context: &mut CommandContext) -> Result<CommandResult> {
+    let (options, source, dest) =
+        parse_transfer_args(context, "Usage: 'cp [-n] [-u] [-p] <source> <dest>'")?;
+    let pairs = transfer_pairs(source, dest)?;
+
+    let mut transfer = Transfer::new(options);
+    for (source, _) in &pairs {
+        transfer.add_to_total(source);
+    }
+    for (source, dest) in &pairs {
+        if let Err(error) = transfer.copy(source, dest) {
+            return Err(transfer.roll_back(error));
         }
     }
-
+    transfer.commit()?;
     Ok(CommandResult::Lovely)
 }
 fn mv(context: &mut CommandContext) -> Result<CommandResult> {
-    if context.args.len() != 2 {
-        Err(std::io::Error::other("Usage: 'mv <source> <dest>'"))?;
-    }
-    cp(context)?;
-    let (matches, source_parent) = match_file_pattern(context.args[0])?;
-    for name in matches {
-        let pathbuf = source_parent.join(name);
-        if pathbuf.is_file() {
-            std::fs::remove_file(pathbuf)?;
-        } else {
-            std::fs::remove_dir_all(pathbuf)?;
+    let (options, source, dest) =
+        parse_transfer_args(context, "Usage: 'mv [-n] [-u] [-p] <source> <dest>'")?;
+    let pairs = transfer_pairs(source, dest)?;
+
+    let mut transfer = Transfer::new(options);
+    for (source, dest) in &pairs {
+        if let Err(error) = transfer.move_item(source, dest) {
+            return Err(transfer.roll_back(error));
         }
     }
+    transfer.commit()?;
     Ok(CommandResult::Lovely)
 }
+/// Patterns matching more items than this will ask for confirmation before being removed
+const RM_CONFIRM_THRESHOLD: usize = 5;
+
+/// Ask the user a yes/no question on the terminal. Defaults to no.
+fn confirm(question: &str) -> Result<bool> {
+    let mut stdout = std::io::stdout();
+    write!(stdout, "{} [y/N] ", question)?;
+    stdout.flush()?;
+    let mut answer = String::new();
+    std::io::stdin().read_line(&mut answer)?;
+    Ok(matches!(answer.trim().to_lowercase().as_str(), "y" | "yes"))
+}
+
 fn rm(context: &mut CommandContext) -> Result<CommandResult> {
-    if context.args.len() != 1 {
-        Err(std::io::Error::other("Usage: 'rm <target>'"))?;
+    let mut force = false;
+    let mut use_trash = false;
+    let mut targets = Vec::new();
+    for arg in context.args {
+        match *arg {
+            "-f" | "--force" => force = true,
+            "-t" | "--trash" => use_trash = true,
+            _ => targets.push(*arg),
+        }
     }
-    let (matches, source_parent) = match_file_pattern(context.args[0])?;
-    if matches.len() == 0 {
-        Err(std::io::Error::other("No item by name/pattern found"))?;
+    if targets.is_empty() {
+        Err(std::io::Error::other(
+            "Usage: 'rm [-f] [-t/--trash] <target> [target...]'",
+        ))?;
     }
-    for name in matches {
-        let pathbuf = source_parent.join(name);
-        let target_is_file = pathbuf.is_file();
-        if target_is_file {
-            std::fs::remove_file(pathbuf)?;
+
+    let mut paths = Vec::new();
+    for target in targets {
+        let (matches, source_parent) = match_file_pattern(target)?;
+        if matches.is_empty() {
+            let message = format!("No item by name/pattern '{}' found", target);
+            Err(std::io::Error::other(message))?;
+        }
+        for name in matches {
+            paths.push(source_parent.join(name));
+        }
+    }
+
+    // ask before removing a lot of items, or directories, unless forced
+    if !force {
+        let dirs = paths.iter().filter(|path| path.is_dir()).count();
+        let question = if paths.len() > RM_CONFIRM_THRESHOLD {
+            Some(format!("Remove {} items?", paths.len()))
+        } else if dirs > 0 {
+            Some(format!(
+                "Remove {} director{} and everything in {}?",
+                dirs,
+                if dirs == 1 { "y" } else { "ies" },
+                if dirs == 1 { "it" } else { "them" }
+            ))
         } else {
+            None
+        };
+        if let Some(question) = question {
+            // nobody can answer if input isn't a terminal, like in scripts
+            if !std::io::stdin().is_terminal() {
+                let message = format!("{} Use -f to remove without asking", question);
+                Err(std::io::Error::other(message))?;
+            }
+            if !confirm(&question)? {
+                Err(std::io::Error::other("Cancelled"))?;
+            }
+        }
+    }
+
+    for pathbuf in paths {
+        if use_trash {
+            trash::move_to_trash(&pathbuf)?;
+        } else if pathbuf.is_dir() && !pathbuf.is_symlink() {
             std::fs::remove_dir_all(pathbuf)?;
+        } else {
+            std::fs::remove_file(pathbuf)?;
         }
     }
     Ok(CommandResult::Lovely)
 }
+fn trash(context: &mut CommandContext) -> Result<CommandResult> {
+    let (format, args) = take_format_flags(context);
+    match args.front() {
+        Some(&"list") => {
+            let mut records = Records::new(&["name", "deleted", "path"]);
+            for item in trash::list_trash()? {
+                let deleted = match item.deleted_at {
+                    Some(time) => Field::time(time),
+                    None => Field::empty(),
+                };
+                records.push(vec![
+                    Field::text(item.name).colored(context.theme.primary_color),
+                    deleted.colored(Color::DarkGrey),
+                    Field::text(item.original_path.to_string_lossy()),
+                ]);
+            }
+            records.write(context, format)?;
+        }
+        Some(&"restore") if args.len() >= 2 => {
+            for name in args.iter().skip(1) {
+                trash::restore(name)?;
+            }
+        }
+        Some(&"empty") => {
+            if confirm("Permanently delete everything in the trash?")? {
+                trash::empty_trash()?;
+            }
+        }
+        _ => Err(std::io::Error::other(
+            "Usage: 'trash list', 'trash restore <name> [name...]' or 'trash empty'",
+        ))?,
+    }
+    Ok(CommandResult::Lovely)
+}
 fn mkdir(context: &mut CommandContext) -> Result<CommandResult> {
     if context.args.len() != 1 {
         Err(std::io::Error::other("Usage: 'mkdir <path>'"))?;
@@ -386,45 +962,262 @@ fn mkdir(context: &mut CommandContext) -> Result<CommandResult> {
     Ok(CommandResult::Lovely)
 }
 fn which(context: &mut CommandContext) -> Result<CommandResult> {
-    if context.args.len() != 1 {
-        Err(std::io::Error::other("Usage: 'which <binary>'"))?;
+    let (format, names) = take_format_flags(context);
+    if names.is_empty() {
+        Err(std::io::Error::other("Usage: 'which <binary> [binary...]'"))?;
+    }
+    let mut records = Records::new(&["name", "path"]);
+    for name in names {
+        let binary = binaryfinder::find_binary(name, context.path_items, context.path_extensions)?;
+        records.push(vec![
+            Field::text(name).colored(context.theme.primary_color),
+            Field::text(binary.to_string_lossy()),
+        ]);
+    }
+    records.write(context, format)?;
+    Ok(CommandResult::Lovely)
+}
+fn env(context: &mut CommandContext) -> Result<CommandResult> {
+    let (format, _) = take_format_flags(context);
+
+    // variables set with export override the ones shoe was started with
+    let mut variables: HashMap<String, String> = std::env::vars().collect();
+    for (key, value) in context.enviroment_variables {
+        variables.insert(key.clone(), value.clone());
+    }
+    let mut variables: Vec<(String, String)> = variables.into_iter().collect();
+    variables.sort();
+
+    let mut records = Records::new(&["name", "value"]);
+    for (key, value) in variables {
+        records.push(vec![
+            Field::text(key).colored(context.theme.primary_color),
+            Field::text(value),
+        ]);
+    }
+    records.write(context, format)?;
+    Ok(CommandResult::Lovely)
+}
+/// Show the default prompt and a highlighted command line in every theme, one theme per line
+fn theme_preview(context: &mut CommandContext) -> Result<CommandResult> {
+    const SAMPLE_INPUT: &str = "grep -n \"fn main\" ./src main.rs";
+    const SAMPLE_SUGGESTION: &str = " | head";
+
+    let (user_themes, _) = theme::load_user_themes();
+    let themes: Vec<Theme> = theme::builtin_themes()
+        .into_iter()
+        .chain(user_themes)
+        .collect();
+    let name_width = themes
+        .iter()
+        .map(|theme| theme.name.len())
+        .max()
+        .unwrap_or(0);
+    let prompt = PromptTemplate::default();
+    for theme in &themes {
+        write!(context.stdout, "{:<name_width$}  ", theme.name)?;
+        let rendered = prompt.render(theme, |placeholder| {
+            match placeholder {
+                Placeholder::Cwd => "~/projects/shoe",
+                Placeholder::ShortCwd => "shoe",
+                Placeholder::Git | Placeholder::Branch => "main",
+                Placeholder::Duration => "1.2s",
+                Placeholder::Status | Placeholder::Jobs => "0",
+                Placeholder::User => "user",
+                Placeholder::Host => "host",
+                Placeholder::Time => "12:00:00",
+                Placeholder::ViMode => "",
+            }
+            .to_string()
+        });
+        write!(context.stdout, "{}", rendered.text)?;
+        write!(
+            context.stdout,
+            "{}",
+            highlight_input(SAMPLE_INPUT, theme, context.config.math, None)
+        )?;
+        queue!(
+            context.stdout,
+            SetForegroundColor(theme.suggestion_color),
+            SetAttribute(Attribute::Italic)
+        )?;
+        write!(context.stdout, "{}", SAMPLE_SUGGESTION)?;
+        queue!(
+            context.stdout,
+            SetAttribute(Attribute::NoItalic),
+            SetForegroundColor(Color::Reset)
+        )?;
+        writeln!(context.stdout)?;
     }
-    let name = context.args[0];
-    let binary = binaryfinder::find_binary(name, context.path_items, context.path_extensions)?;
-    writeln!(context.stdout, "{}", binary.to_string_lossy())?;
     Ok(CommandResult::Lovely)
 }
 fn theme(context: &mut CommandContext) -> Result<CommandResult> {
+    if context.args.front() == Some(&"preview") {
+        return theme_preview(context);
+    }
     if context.args.len() != 1 {
-        writeln!(context.stdout, "Usage: 'theme <theme name>'")?;
-        writeln!(context.stdout, "Available themes: ")?;
-        for theme in THEMES {
-            // if theme is active theme, print with color
-            if std::ptr::eq(theme, context.theme) {
-                queue!(
-                    context.stdout,
-                    SetForegroundColor(context.theme.primary_color)
-                )?;
-                writeln!(context.stdout, "\t* {}", theme.name)?;
-                queue!(context.stdout, SetForegroundColor(Color::Reset))?;
-            } else {
-                writeln!(context.stdout, "\t* {}", theme.name)?;
+        writeln!(
+            context.stdout,
+            "Usage: 'theme <theme name>' or 'theme preview'"
+        )?;
+        let (user_themes, errors) = theme::load_user_themes();
+        let lists: [(&str, &[Theme]); 2] = [
+            ("Available themes: ", &theme::builtin_themes()),
+            ("User themes (from ~/.config/shoe/themes): ", &user_themes),
+        ];
+        for (title, list) in lists {
+            if list.is_empty() {
+                continue;
+            }
+            writeln!(context.stdout, "{}", title)?;
+            for theme in list {
+                // if theme is active theme, print with color
+                if theme.name == context.theme.name {
+                    queue!(
+                        context.stdout,
+                        SetForegroundColor(context.theme.primary_color)
+                    )?;
+                    writeln!(context.stdout, "\t* {}", theme.name)?;
+                    queue!(context.stdout, SetForegroundColor(Color::Reset))?;
+                } else {
+                    writeln!(context.stdout, "\t* {}", theme.name)?;
+                }
             }
         }
+        // show which theme files are broken, but still list the rest
+        for error in errors {
+            queue!(context.stdout, SetForegroundColor(context.theme.err_color))?;
+            writeln!(context.stdout, "{}", error)?;
+            queue!(context.stdout, SetForegroundColor(Color::Reset))?;
+        }
         Ok(CommandResult::Lovely)
     } else {
         let theme_name = context.args[0];
-
-        for (index, theme) in THEMES.iter().enumerate() {
-            if theme.name == theme_name {
-                return Ok(CommandResult::UpdateTheme(index));
+        match theme::find_theme(theme_name) {
+            Some(theme) => Ok(CommandResult::UpdateTheme(theme)),
+            None => {
+                let message = format!("No theme by name '{}'", theme_name);
+                Err(std::io::Error::other(message))
             }
         }
-        let message = format!("No theme by name '{}'", theme_name);
-        Err(std::io::Error::other(message))
     }
 }
 
+fn config(context: &mut CommandContext) -> Result<CommandResult> {
+    match context.args.front() {
+        Some(&"get") => {
+            let Some(key) = context.args.get(1) else {
+                Err(std::io::Error::other("Usage: 'config get <setting>'"))?
+            };
+            let settings = context.config.settings();
+            let Some((_, value)) = settings.iter().find(|(name, _)| name == key) else {
+                let message = format!(
+                    "Unknown setting '{}'. Run 'config' to see all settings",
+                    key
+                );
+                Err(std::io::Error::other(message))?
+            };
+            writeln!(context.stdout, "{}", config::format_value(value))?;
+            return Ok(CommandResult::Lovely);
+        }
+        Some(&"set") => {
+            let Some(key) = context.args.get(1) else {
+                Err(std::io::Error::other(
+                    "Usage: 'config set <setting> <value>'",
+                ))?
+            };
+            let values: Vec<&str> = context.args.iter().skip(2).copied().collect();
+            let config = config::set(key, &values)?;
+            return Ok(CommandResult::UpdateConfig(config));
+        }
+        _ => {}
+    }
+    let (format, _) = take_format_flags(context);
+    let mut records = Records::new(&["setting", "value"]);
+    for (key, value) in context.config.settings() {
+        records.push(vec![
+            Field::text(key).colored(context.theme.primary_color),
+            Field::text(config::format_value(&value)),
+        ]);
+    }
+    records.write(context, format)?;
+    Ok(CommandResult::Lovely)
+}
+
+fn history(context: &mut CommandContext) -> Result<CommandResult> {
+    match context.args.front() {
+        Some(&"clear") => return Ok(CommandResult::ClearHistory),
+        Some(&"delete") => {
+            let index = context
+                .args
+                .get(1)
+                .and_then(|arg| arg.parse::<usize>().ok());
+            // entries are shown starting from 1
+            let Some(index) = index.filter(|index| *index >= 1 && *index <= context.history.len())
+            else {
+                Err(std::io::Error::other(
+                    "Usage: 'history delete <n>', where n is the number shown by 'history'",
+                ))?
+            };
+            return Ok(CommandResult::DeleteHistoryEntry(index - 1));
+        }
+        _ => {}
+    }
+    let (format, args) = take_format_flags(context);
+    let mut only_cwd = false;
+    let mut only_failed = false;
+    let mut search: Vec<&str> = Vec::new();
+    for arg in args {
+        match arg {
+            "--cwd" => only_cwd = true,
+            "--failed" => only_failed = true,
+            _ => search.push(arg),
+        }
+    }
+    let search = search.join(" ");
+    let cwd = absolute_pathbuf_to_string(&std::env::current_dir()?);
+
+    let mut records = Records::new(&["index", "time", "duration", "exit", "cwd", "command"]);
+    for (index, entry) in context.history.iter().enumerate() {
+        if only_cwd && entry.cwd != cwd {
+            continue;
+        }
+        if only_failed && entry.exit_code == 0 {
+            continue;
+        }
+        if !entry.command.contains(&search) {
+            continue;
+        }
+
+        // migrated entries from the old history format have no timestamp or duration
+        let (time, duration) = if entry.timestamp == 0 {
+            (Field::empty(), Field::empty())
+        } else {
+            (
+                Field::time(entry.timestamp as i64),
+                Field::duration(entry.duration),
+            )
+        };
+
+        // show index of failed commands in the error color
+        let index_color = if entry.exit_code == 0 {
+            context.theme.primary_color
+        } else {
+            context.theme.err_color
+        };
+        records.push(vec![
+            Field::integer(index as i64 + 1).colored(index_color),
+            time.colored(Color::DarkGrey),
+            duration.colored(Color::DarkGrey),
+            Field::integer(entry.exit_code as i64).colored(Color::DarkGrey),
+            Field::text(&entry.cwd).colored(Color::DarkGrey),
+            Field::text(&entry.command),
+        ]);
+    }
+    records.write(context, format)?;
+    Ok(CommandResult::Lovely)
+}
+
 type CommandFunction = &'static dyn Fn(&mut CommandContext) -> Result<CommandResult>;
 
 /// Const array of all builtin functions as key value pairs of their name and function reference
@@ -434,19 +1227,36 @@ pub const COMMANDS: &[(&str, CommandFunction)] = &[
     ("ls", &ls),
     ("export", &export),
     ("cd", &cd),
+    ("pushd", &pushd),
+    ("popd", &popd),
+    ("dirs", &dirs),
+    ("z", &z),
     ("pwd", &pwd),
     ("echo", &echo),
     ("column", &column),
     ("cls", &cls),
     ("cat", &cat),
+    ("view", &view),
+    ("grep", &filters::grep),
+    ("head", &filters::head),
+    ("tail", &filters::tail),
+    ("wc", &filters::wc),
+    ("sort", &filters::sort),
+    ("uniq", &filters::uniq),
+    ("find", &find::find),
     ("cp", &cp),
     ("mv", &mv),
     ("rm", &rm),
+    ("trash", &trash),
     ("help", &help),
     ("mkdir", &mkdir),
     ("theme", &theme),
+    ("prompt", &prompt),
+    ("config", &config),
     ("which", &which),
+    ("env", &env),
     ("copy", &copy),
+    ("history", &history),
     ("exit", &|_| Ok(CommandResult::Exit)),
 ];
 
@@ -469,11 +1279,24 @@ pub fn execute_command(keyword: &str, context: &mut CommandContext) -> Result<Co
 /// Context passed to builtin commands
 pub struct CommandContext<'a> {
     pub args: &'a VecDeque<&'a str>,
-    pub theme: &'static Theme,
+    pub theme: &'a Theme,
+    pub config: &'a Config,
     pub stdout: &'a mut Vec<u8>,
     pub stdin: Vec<u8>,
     pub path_items: &'a HashMap<String, PathBuf>,
     pub path_extensions: &'a Vec<String>,
+    pub history: &'a [HistoryEntry],
+    pub enviroment_variables: &'a HashMap<String, String>,
+    /// Whether the command's output is shown directly on the terminal, and not piped or redirected to a file
+    pub output_is_terminal: bool,
+    /// Set by the command if its output should be kept exactly as is, and not have ansi escape codes stripped when redirected
+    pub raw_output: bool,
+    /// The directory before the last directory change
+    pub previous_directory: Option<&'a PathBuf>,
+    /// Directories pushed by `pushd`, most recent last
+    pub directory_stack: &'a [PathBuf],
+    /// Directories visited before, for `z`
+    pub visited_directories: &'a [VisitedDirectory],
 }
 
 /// Result from a builtin command
@@ -482,26 +1305,32 @@ pub struct CommandContext<'a> {
 pub enum CommandResult {
     /// Default/OK state, means command executed sucessfully and nothing needs to be done
     Lovely,
+    /// The command ran without errors, but should still count as failed, like grep finding nothing. No error is printed
+    Failed,
     /// Means the command was `exit` and the shell should close
     Exit,
-    /// The command requests to update the theme. The usize is the theme index
-    UpdateTheme(usize),
+    /// The command requests to update the theme
+    UpdateTheme(Theme),
     /// Input was not a builtin command
     NotACommand,
     /// The command requests to insert an enviroment variable into the registry
     SetEnvVar(String, String),
-}
-
-/// Recursively copy a directory
-fn copy_dir(source: impl AsRef<Path>, dest: impl AsRef<Path>) -> std::io::Result<()> {
-    fs::create_dir_all(&dest)?;
-    for item in fs::read_dir(source)?.flatten() {
-        let is_file = item.file_type()?.is_file();
-        if is_file {
-            fs::copy(item.path(), dest.as_ref().join(item.file_name()))?;
-        } else {
-            copy_dir(item.path(), dest.as_ref().join(item.file_name()))?;
-        }
-    }
-    Ok(())
+    /// The command requests to delete the history entry at the index
+    DeleteHistoryEntry(usize),
+    /// The command requests to delete all history
+    ClearHistory,
+    /// The command requests to run these commands, one after another
+    RunCommands(Vec<String>),
+    /// The command requests to change the working directory
+    ChangeDirectory(PathBuf),
+    /// The command requests to change the working directory, and push the current one onto the directory stack
+    PushDirectory(PathBuf),
+    /// The command requests to go back to the directory on top of the directory stack
+    PopDirectory,
+    /// The command requests to go to the directory on top of the directory stack, and replace it with the current one
+    SwapDirectory,
+    /// The command requests to change a prompt. None means the default main prompt, or no right/transient prompt
+    SetPrompt(PromptKind, Option<PromptTemplate>),
+    /// The config file was changed, and the shell should use the new settings
+    UpdateConfig(Config),
 }
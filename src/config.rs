@@ -0,0 +1,559 @@
+//! Settings, from ~/.config/shoe/config.toml
+//!
+//! The file is checked when shoe starts, and settings can be changed with `config set`, which keeps the rest of the file
+//! (including comments) as it is. Everything is optional, for example:
+//!
+//! ```toml
+//! suggestions = true
+//! theme = "ocean"
+//!
+//! [prompt]
+//! right = "{dark_grey}{time}"
+//!
+//! [history]
+//! size = 10000
+//! ignore = ["*TOKEN=*"]
+//!
+//! [keybindings]
+//! "ctrl+h" = "backspace"
+//! ```
+
+use std::{collections::BTreeMap, fs, io, path::PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    history::IgnorePattern,
+    keybindings::KeyMap,
+    prompt::{PromptKind, PromptTemplate},
+    theme::{self, Theme},
+    utils::config_dir,
+};
+
+#[derive(Clone, Deserialize, PartialEq, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// Show inline suggestions from history and completions
+    pub suggestions: bool,
+    /// Replace ~ with the home directory in commands
+    pub substitute_tildes: bool,
+    /// Evaluate input that is a math expression, like `2 + 2`
+    pub math: bool,
+    /// Seconds a command has to run for its duration to be shown, or `off`
+    pub duration_threshold: DurationThreshold,
+    /// Name of the theme to use. Empty means the default theme
+    pub theme: String,
+    pub prompt: PromptConfig,
+    pub history: HistoryConfig,
+    pub completion: CompletionConfig,
+    /// How keys edit the input: `emacs` (the default) or `vi`
+    pub edit_mode: String,
+    /// Keys bound to actions or to other keys, like `"ctrl+k" = "kill-line"` or `"ctrl+h" = "backspace"`
+    pub keybindings: BTreeMap<String, String>,
+}
+
+/// Prompt templates. Empty means the default prompt, or no right/transient prompt
+#[derive(Clone, Default, Deserialize, PartialEq, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PromptConfig {
+    pub main: String,
+    pub right: String,
+    pub transient: String,
+}
+
+#[derive(Clone, Default, Deserialize, PartialEq, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct HistoryConfig {
+    /// Most commands to keep in history. Older commands are removed when shoe starts. 0 means no limit
+    pub size: usize,
+    /// Patterns of commands to keep out of history, in addition to the ones in ~/.shoehistoryignore
+    pub ignore: Vec<String>,
+}
+
+#[derive(Clone, Deserialize, PartialEq, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CompletionConfig {
+    /// Ignore case when completing paths
+    pub ignore_case: bool,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            suggestions: true,
+            substitute_tildes: true,
+            math: true,
+            duration_threshold: DurationThreshold::Seconds(5.0),
+            theme: String::new(),
+            prompt: PromptConfig::default(),
+            history: HistoryConfig::default(),
+            completion: CompletionConfig::default(),
+            edit_mode: String::from("emacs"),
+            keybindings: BTreeMap::new(),
+        }
+    }
+}
+
+/// How long a command has to run for its duration to be shown
+#[derive(Clone, Deserialize, PartialEq)]
+#[serde(untagged)]
+pub enum DurationThreshold {
+    Seconds(f64),
+    /// Should be `off`, to never show durations
+    Off(String),
+}
+
+impl DurationThreshold {
+    /// In milliseconds, or Some(None) if durations shouldn't be shown. None if the threshold isn't valid
+    fn millis(&self) -> Option<Option<u64>> {
+        match self {
+            DurationThreshold::Seconds(seconds) if seconds.is_finite() && *seconds >= 0.0 => {
+                Some(Some((seconds * 1000.0) as u64))
+            }
+            DurationThreshold::Off(text) if text == "off" => Some(None),
+            _ => None,
+        }
+    }
+}
+
+impl Serialize for DurationThreshold {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self {
+            // whole seconds are written without a fraction, like they are usually typed
+            DurationThreshold::Seconds(seconds)
+                if seconds.fract() == 0.0 && (0.0..=u32::MAX as f64).contains(seconds) =>
+            {
+                serializer.serialize_u64(*seconds as u64)
+            }
+            DurationThreshold::Seconds(seconds) => serializer.serialize_f64(*seconds),
+            DurationThreshold::Off(text) => serializer.serialize_str(text),
+        }
+    }
+}
+
+impl Default for CompletionConfig {
+    fn default() -> Self {
+        CompletionConfig {
+            // paths on windows aren't case sensitive
+            ignore_case: std::env::consts::OS == "windows",
+        }
+    }
+}
+
+fn invalid_setting(key: &str, error: impl std::fmt::Display) -> io::Error {
+    io::Error::other(format!("Invalid setting {}: {}", key, error))
+}
+
+fn invalid_file(error: impl std::fmt::Display) -> io::Error {
+    let message = format!("Invalid config file {}: {}", config_path().display(), error);
+    io::Error::other(message)
+}
+
+impl Config {
+    /// Read the config file, checking that every setting is valid. If there is no config file, the default settings are used.
+    ///
+    /// Invalid settings are left at their default, and returned as errors, so one mistake doesn't undo every other setting
+    pub fn load() -> io::Result<(Self, Vec<io::Error>)> {
+        let (config, errors) = match fs::read_to_string(config_path()) {
+            Ok(text) => parse_leniently(&text)?,
+            Err(error) if error.kind() == io::ErrorKind::NotFound => {
+                (Config::default(), Vec::new())
+            }
+            Err(error) => return Err(error),
+        };
+        let errors = errors.into_iter().map(|(_, error)| error).collect();
+        Ok((migrate_theme_file(config), errors))
+    }
+
+    /// Check the settings that can't be checked by their type alone
+    fn validate(&self) -> io::Result<()> {
+        self.duration_threshold()?;
+        self.theme()?;
+        for kind in [PromptKind::Main, PromptKind::Right, PromptKind::Transient] {
+            self.prompt(kind)?;
+        }
+        self.ignore_patterns()?;
+        self.keymap()?;
+        Ok(())
+    }
+
+    /// How long (in milliseconds) a command has to run for its duration to be shown. None if durations shouldn't be shown
+    pub fn duration_threshold(&self) -> io::Result<Option<u64>> {
+        self.duration_threshold.millis().ok_or_else(|| {
+            invalid_setting("duration_threshold", "must be a number of seconds, or off")
+        })
+    }
+
+    /// The theme set in the config, if any
+    pub fn theme(&self) -> io::Result<Option<Theme>> {
+        if self.theme.is_empty() {
+            return Ok(None);
+        }
+        match theme::find_theme(&self.theme) {
+            Some(theme) => Ok(Some(theme)),
+            None => {
+                let error = io::Error::other(format!("No theme by name '{}'", self.theme));
+                Err(invalid_setting("theme", error))
+            }
+        }
+    }
+
+    /// The prompt template of a kind set in the config, if any
+    pub fn prompt(&self, kind: PromptKind) -> io::Result<Option<PromptTemplate>> {
+        let (key, template) = match kind {
+            PromptKind::Main => ("prompt.main", &self.prompt.main),
+            PromptKind::Right => ("prompt.right", &self.prompt.right),
+            PromptKind::Transient => ("prompt.transient", &self.prompt.transient),
+        };
+        if template.is_empty() {
+            return Ok(None);
+        }
+        let template = PromptTemplate::parse_kind(template, kind)
+            .map_err(|error| invalid_setting(key, error))?;
+        Ok(Some(template))
+    }
+
+    pub fn ignore_patterns(&self) -> io::Result<Vec<IgnorePattern>> {
+        self.history
+            .ignore
+            .iter()
+            .map(|pattern| IgnorePattern::parse(pattern))
+            .collect::<io::Result<Vec<IgnorePattern>>>()
+            .map_err(|error| invalid_setting("history.ignore", error))
+    }
+
+    pub fn vi_mode(&self) -> bool {
+        self.edit_mode == "vi"
+    }
+
+    pub fn keymap(&self) -> io::Result<KeyMap> {
+        if !matches!(self.edit_mode.as_str(), "emacs" | "vi") {
+            let error = io::Error::other(format!(
+                "'{}' isn't an edit mode, use emacs or vi",
+                self.edit_mode
+            ));
+            return Err(invalid_setting("edit_mode", error));
+        }
+        let bindings: Vec<(String, String)> = self
+            .keybindings
+            .iter()
+            .map(|(key, target)| (key.clone(), target.clone()))
+            .collect();
+        KeyMap::new(&bindings, self.vi_mode())
+            .map_err(|error| invalid_setting("keybindings", error))
+    }
+
+    /// Every setting and its value, with keys in tables written like `history.size`
+    pub fn settings(&self) -> Vec<(String, toml::Value)> {
+        let mut settings = Vec::new();
+        let table = toml::Table::try_from(self).unwrap_or_default();
+        for (key, value) in table {
+            match value {
+                toml::Value::Table(table) => {
+                    for (name, value) in table {
+                        settings.push((format!("{}.{}", key, name), value));
+                    }
+                }
+                value => settings.push((key, value)),
+            }
+        }
+        settings
+    }
+}
+
+pub fn config_path() -> PathBuf {
+    config_dir().join("config.toml")
+}
+
+/// Parse a config, leaving settings that aren't valid at their default. Returns the names and errors of those settings.
+///
+/// Only fails if the file isn't valid TOML at all
+fn parse_leniently(text: &str) -> io::Result<(Config, Vec<(String, io::Error)>)> {
+    let table: toml::Table = text.parse().map_err(invalid_file)?;
+    // settings are added one at a time, and only kept if the config is still valid with them
+    let mut valid = toml::Table::new();
+    let mut config = Config::default();
+    let mut errors = Vec::new();
+    for (key, value) in table {
+        let settings = match value {
+            toml::Value::Table(table) => table
+                .into_iter()
+                .map(|(name, value)| (key.clone(), Some(name), value))
+                .collect(),
+            value => vec![(key, None, value)],
+        };
+        for (key, name, value) in settings {
+            let mut candidate = valid.clone();
+            let full_name = match name {
+                Some(name) => {
+                    let table = candidate
+                        .entry(key.clone())
+                        .or_insert_with(|| toml::Table::new().into());
+                    // a table was checked to be a table when it was added
+                    if let Some(table) = table.as_table_mut() {
+                        table.insert(name.clone(), value);
+                    }
+                    format!("{}.{}", key, name)
+                }
+                None => {
+                    candidate.insert(key.clone(), value);
+                    key
+                }
+            };
+            let checked = toml::Value::Table(candidate.clone())
+                .try_into::<Config>()
+                .map_err(|error| invalid_setting(&full_name, error))
+                .and_then(|checked| checked.validate().map(|_| checked));
+            match checked {
+                Ok(checked) => {
+                    valid = candidate;
+                    config = checked;
+                }
+                Err(error) => errors.push((full_name, error)),
+            }
+        }
+    }
+    Ok((config, errors))
+}
+
+/// Parse a duration threshold in seconds, or `off`, to milliseconds. None if it isn't valid
+pub fn parse_duration_threshold(value: &str) -> Option<Option<u64>> {
+    match value.trim() {
+        "off" => Some(None),
+        value => DurationThreshold::Seconds(value.parse().ok()?).millis(),
+    }
+}
+
+/// Older versions remembered the chosen theme in ~/.config/shoe/theme. Move it to the config, unless the config already has a theme
+fn migrate_theme_file(config: Config) -> Config {
+    let path = config_dir().join("theme");
+    let Ok(name) = fs::read_to_string(&path) else {
+        return config;
+    };
+    let name = name.trim();
+    if !config.theme.is_empty() || name.is_empty() {
+        let _ = fs::remove_file(path);
+        return config;
+    }
+    // if it can't be moved, try again next time
+    match set("theme", &[name]) {
+        Ok(config) => {
+            let _ = fs::remove_file(path);
+            config
+        }
+        Err(_) => config,
+    }
+}
+
+/// Format a setting's value for showing to the user. Strings are shown without quotes
+pub fn format_value(value: &toml::Value) -> String {
+    match value {
+        toml::Value::String(text) => text.clone(),
+        value => value.to_string(),
+    }
+}
+
+/// Convert the args given to `config set` to a value of the same type as the setting has
+fn parse_value(key: &str, values: &[&str]) -> io::Result<toml_edit::Value> {
+    let joined = values.join(" ");
+    // keybindings can have any key
+    if key.starts_with("keybindings.") {
+        return Ok(joined.into());
+    }
+    // a number of seconds, or off
+    if key == "duration_threshold" {
+        let value = match parse_duration_threshold(&joined) {
+            Some(None) => "off".into(),
+            Some(Some(_)) => {
+                let seconds: f64 = joined.trim().parse().unwrap_or_default();
+                if seconds.fract() == 0.0 && seconds <= u32::MAX as f64 {
+                    (seconds as i64).into()
+                } else {
+                    seconds.into()
+                }
+            }
+            None => {
+                let message = format!("{} must be a number of seconds, or off", key);
+                return Err(io::Error::other(message));
+            }
+        };
+        return Ok(value);
+    }
+    let default = Config::default()
+        .settings()
+        .into_iter()
+        .find(|(name, _)| name == key)
+        .map(|(_, value)| value)
+        .ok_or_else(|| {
+            io::Error::other(format!(
+                "Unknown setting '{}'. Run 'config' to see all settings",
+                key
+            ))
+        })?;
+    let value = match default {
+        toml::Value::Boolean(_) => match joined.as_str() {
+            "true" => true.into(),
+            "false" => false.into(),
+            _ => {
+                let message = format!("{} must be true or false", key);
+                return Err(io::Error::other(message));
+            }
+        },
+        toml::Value::Integer(_) => joined
+            .parse::<i64>()
+            .map_err(|_| io::Error::other(format!("{} must be a whole number", key)))?
+            .into(),
+        // every arg is an item of the list
+        toml::Value::Array(_) => values.iter().copied().collect::<toml_edit::Array>().into(),
+        _ => joined.into(),
+    };
+    Ok(value)
+}
+
+/// Change a setting in the config file, keeping the rest of the file as it is.
+///
+/// The file is only written if the new value is valid. Returns the new config
+pub fn set(key: &str, values: &[&str]) -> io::Result<Config> {
+    let path = config_path();
+    let text = match fs::read_to_string(&path) {
+        Ok(text) => text,
+        Err(error) if error.kind() == io::ErrorKind::NotFound => String::new(),
+        Err(error) => return Err(error),
+    };
+    let (text, config) = set_in_text(&text, key, values)?;
+    fs::create_dir_all(config_dir())?;
+    fs::write(&path, text)?;
+    Ok(config)
+}
+
+/// Change a setting in the text of a config file. Returns the new text, and the config it has.
+///
+/// Fails if the new value isn't valid. Other invalid settings are left at their default, like when the config is loaded
+fn set_in_text(text: &str, key: &str, values: &[&str]) -> io::Result<(String, Config)> {
+    let mut document: toml_edit::DocumentMut = text.parse().map_err(invalid_file)?;
+
+    let value = parse_value(key, values)?;
+    match key.split_once('.') {
+        Some((table_name, name)) => {
+            let table = document
+                .entry(table_name)
+                .or_insert(toml_edit::table())
+                .as_table_like_mut()
+                .ok_or_else(|| io::Error::other(format!("{} is not a table", table_name)))?;
+            table.insert(name, toml_edit::Item::Value(value));
+        }
+        None => document[key] = toml_edit::Item::Value(value),
+    }
+
+    let text = document.to_string();
+    let (config, errors) = parse_leniently(&text)?;
+    if let Some((_, error)) = errors.into_iter().find(|(name, _)| name == key) {
+        return Err(error);
+    }
+    Ok((text, config))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_duration_thresholds() {
+        assert_eq!(parse_duration_threshold("5"), Some(Some(5000)));
+        assert_eq!(parse_duration_threshold(" 0.5 "), Some(Some(500)));
+        assert_eq!(parse_duration_threshold("off"), Some(None));
+        assert_eq!(parse_duration_threshold("-1"), None);
+        assert_eq!(parse_duration_threshold("soon"), None);
+    }
+
+    #[test]
+    fn reads_duration_thresholds_of_any_type() {
+        for (text, expected) in [
+            ("duration_threshold = 10", Some(10000)),
+            ("duration_threshold = 2.5", Some(2500)),
+            ("duration_threshold = \"off\"", None),
+        ] {
+            let (config, errors) = parse_leniently(text).unwrap();
+            assert!(errors.is_empty(), "{}", text);
+            assert_eq!(config.duration_threshold().unwrap(), expected, "{}", text);
+        }
+        let (_, errors) = parse_leniently("duration_threshold = \"5\"").unwrap();
+        assert_eq!(errors.len(), 1);
+    }
+
+    #[test]
+    fn parses_values_like_the_setting() {
+        assert_eq!(
+            parse_value("math", &["false"]).unwrap().as_bool(),
+            Some(false)
+        );
+        assert!(parse_value("math", &["maybe"]).is_err());
+        assert_eq!(
+            parse_value("history.size", &["100"]).unwrap().as_integer(),
+            Some(100)
+        );
+        assert!(parse_value("history.size", &["lots"]).is_err());
+        let ignore = parse_value("history.ignore", &["a*", "b*"]).unwrap();
+        assert_eq!(ignore.as_array().map(|array| array.len()), Some(2));
+        assert_eq!(
+            parse_value("duration_threshold", &["10"])
+                .unwrap()
+                .as_integer(),
+            Some(10)
+        );
+        assert_eq!(
+            parse_value("duration_threshold", &["1.5"])
+                .unwrap()
+                .as_float(),
+            Some(1.5)
+        );
+        assert_eq!(
+            parse_value("duration_threshold", &["off"])
+                .unwrap()
+                .as_str(),
+            Some("off")
+        );
+        assert!(parse_value("duration_threshold", &["never"]).is_err());
+        assert!(parse_value("colour", &["red"]).is_err());
+    }
+
+    #[test]
+    fn sets_settings_keeping_the_rest() {
+        let text = "# my settings\nmath = false\n\n[history]\nsize = 5 # small\n";
+        let (text, config) = set_in_text(text, "duration_threshold", &["10"]).unwrap();
+        assert!(text.starts_with("# my settings\nmath = false\n"));
+        assert!(text.contains("size = 5 # small"));
+        assert!(text.contains("duration_threshold = 10"));
+        assert_eq!(config.duration_threshold().unwrap(), Some(10000));
+        assert!(!config.math);
+
+        let (text, config) = set_in_text(&text, "history.size", &["20"]).unwrap();
+        assert!(text.contains("size = 20"));
+        assert_eq!(config.history.size, 20);
+
+        assert!(set_in_text(&text, "edit_mode", &["nano"]).is_err());
+        // other invalid settings don't stop a setting from being changed
+        let text = "edit_mode = \"nano\"\n";
+        let (_, config) = set_in_text(text, "math", &["false"]).unwrap();
+        assert!(!config.math);
+        assert_eq!(config.edit_mode, "emacs");
+    }
+
+    #[test]
+    fn invalid_settings_fall_back_to_their_default() {
+        let text = "math = false\nedit_mode = \"nano\"\nsuggestions = 3\n\n[history]\nsize = 20\nignore = [\"re:(\"]\n";
+        let (config, errors) = parse_leniently(text).unwrap();
+        assert!(!config.math);
+        assert_eq!(config.history.size, 20);
+        assert_eq!(config.edit_mode, "emacs");
+        assert!(config.suggestions);
+        assert!(config.history.ignore.is_empty());
+        let messages: Vec<String> = errors.iter().map(|(_, error)| error.to_string()).collect();
+        assert_eq!(messages.len(), 3, "{:?}", messages);
+        assert!(messages[0].starts_with("Invalid setting edit_mode"));
+        assert!(messages[1].starts_with("Invalid setting history.ignore"));
+        assert!(messages[2].starts_with("Invalid setting suggestions"));
+
+        assert!(parse_leniently("math = ").is_err());
+    }
+}
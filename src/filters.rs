@@ -0,0 +1,377 @@
+//! Builtins for processing text: `grep`, `head`, `tail`, `wc`, `sort` and `uniq`
+//!
+//! They read the files given as args, or stdin if there are none, so they can be used in pipelines.
+//! Since they don't rely on any programs being installed, they behave the same on every OS.
+
+use std::{
+    cmp::Ordering,
+    fs,
+    io::{Result, Write},
+};
+
+use crossterm::{
+    queue,
+    style::{Color, SetForegroundColor},
+};
+
+use crate::commands::{CommandContext, CommandResult};
+
+/// Lines shown by `head` and `tail` by default
+const DEFAULT_LINE_COUNT: usize = 10;
+
+/// Text to process, with the name of where it came from
+struct Input {
+    name: String,
+    data: Vec<u8>,
+}
+
+impl Input {
+    fn text(&self) -> String {
+        String::from_utf8_lossy(&self.data).to_string()
+    }
+}
+
+/// Read every file, or stdin if no files are given
+fn read_inputs(context: &mut CommandContext, files: &[&str]) -> Result<Vec<Input>> {
+    if files.is_empty() {
+        return Ok(vec![Input {
+            name: String::from("stdin"),
+            data: std::mem::take(&mut context.stdin),
+        }]);
+    }
+    let mut inputs = Vec::new();
+    for file in files {
+        inputs.push(Input {
+            name: file.to_string(),
+            data: fs::read(file)?,
+        });
+    }
+    Ok(inputs)
+}
+
+/// A short flag, with its value if it takes one
+type Flag<'a> = (char, Option<&'a str>);
+
+/// Split args into short flags and other args. Short flags can be combined, like `-in`.
+///
+/// Flags in `with_value` take the next arg (or the rest of the combined flag) as their value.
+/// Everything after `--` is taken as other args, so patterns and files can start with `-`.
+fn parse_flags<'a>(
+    mut args: impl Iterator<Item = &'a str>,
+    with_value: &[char],
+) -> Result<(Vec<Flag<'a>>, Vec<&'a str>)> {
+    let mut flags = Vec::new();
+    let mut rest = Vec::new();
+    while let Some(arg) = args.next() {
+        if arg == "--" {
+            rest.extend(args);
+            break;
+        }
+        let Some(chars) = arg.strip_prefix('-').filter(|chars| !chars.is_empty()) else {
+            rest.push(arg);
+            continue;
+        };
+        // numbers like -5 are treated as -n 5
+        if with_value.contains(&'n') && chars.chars().all(|c| c.is_ascii_digit()) {
+            flags.push(('n', Some(chars)));
+            continue;
+        }
+        for (index, flag) in chars.char_indices() {
+            if !with_value.contains(&flag) {
+                flags.push((flag, None));
+                continue;
+            }
+            let value = &chars[index + flag.len_utf8()..];
+            let value = if value.is_empty() {
+                args.next()
+                    .ok_or_else(|| std::io::Error::other(format!("Flag -{} needs a value", flag)))?
+            } else {
+                value
+            };
+            flags.push((flag, Some(value)));
+            break;
+        }
+    }
+    Ok((flags, rest))
+}
+
+fn unknown_flag(flag: char) -> std::io::Error {
+    std::io::Error::other(format!("Unknown flag -{}", flag))
+}
+
+/// Parse the value of the -n flag
+fn parse_count(value: Option<&str>) -> Result<usize> {
+    let value = value.unwrap_or_default();
+    value
+        .parse()
+        .map_err(|_| std::io::Error::other(format!("Invalid line count '{}'", value)))
+}
+
+/// Write text in a color, or uncolored if there is no color
+fn write_colored(stdout: &mut Vec<u8>, text: &str, color: Option<Color>) -> Result<()> {
+    match color {
+        Some(color) => {
+            queue!(stdout, SetForegroundColor(color))?;
+            write!(stdout, "{}", text)?;
+            queue!(stdout, SetForegroundColor(Color::Reset))
+        }
+        None => write!(stdout, "{}", text),
+    }
+}
+
+pub fn grep(context: &mut CommandContext) -> Result<CommandResult> {
+    let (flags, mut rest) = parse_flags(context.args.iter().copied(), &[])?;
+    let mut ignore_case = false;
+    let mut invert = false;
+    let mut line_numbers = false;
+    for (flag, _) in flags {
+        match flag {
+            'i' => ignore_case = true,
+            'v' => invert = true,
+            'n' => line_numbers = true,
+            _ => return Err(unknown_flag(flag)),
+        }
+    }
+    if rest.is_empty() {
+        return Err(std::io::Error::other("Missing pattern"));
+    }
+    let pattern = rest.remove(0);
+    let regex = regex::RegexBuilder::new(pattern)
+        .case_insensitive(ignore_case)
+        .build()
+        .map_err(|error| std::io::Error::other(format!("Invalid pattern: {}", error)))?;
+
+    let inputs = read_inputs(context, &rest)?;
+    let show_names = inputs.len() > 1;
+    let highlight = context.output_is_terminal;
+    let mut found_any = false;
+    for input in inputs {
+        for (index, line) in input.text().lines().enumerate() {
+            if regex.is_match(line) == invert {
+                continue;
+            }
+            found_any = true;
+            let prefix_color = highlight.then_some(context.theme.secondary_color);
+            if show_names {
+                write_colored(context.stdout, &format!("{}:", input.name), prefix_color)?;
+            }
+            if line_numbers {
+                write_colored(context.stdout, &format!("{}:", index + 1), prefix_color)?;
+            }
+            if !highlight || invert {
+                writeln!(context.stdout, "{}", line)?;
+                continue;
+            }
+            // color the matched parts of the line
+            let mut last_end = 0;
+            for found in regex.find_iter(line) {
+                write!(context.stdout, "{}", &line[last_end..found.start()])?;
+                write_colored(
+                    context.stdout,
+                    found.as_str(),
+                    Some(context.theme.primary_color),
+                )?;
+                last_end = found.end();
+            }
+            writeln!(context.stdout, "{}", &line[last_end..])?;
+        }
+    }
+    // like grep, finding nothing counts as failing, so it can be used as a condition with && and ||
+    if !found_any {
+        return Ok(CommandResult::Failed);
+    }
+    Ok(CommandResult::Lovely)
+}
+
+/// Shared by `head` and `tail`, which only differ in which end of the input they take lines from
+fn head_or_tail(context: &mut CommandContext, from_end: bool) -> Result<CommandResult> {
+    let (flags, files) = parse_flags(context.args.iter().copied(), &['n'])?;
+    let mut count = DEFAULT_LINE_COUNT;
+    for (flag, value) in flags {
+        match flag {
+            'n' => count = parse_count(value)?,
+            _ => return Err(unknown_flag(flag)),
+        }
+    }
+
+    let inputs = read_inputs(context, &files)?;
+    let show_names = inputs.len() > 1;
+    for (index, input) in inputs.iter().enumerate() {
+        if show_names {
+            if index > 0 {
+                writeln!(context.stdout)?;
+            }
+            writeln!(context.stdout, "==> {} <==", input.name)?;
+        }
+        let text = input.text();
+        let lines: Vec<&str> = text.lines().collect();
+        let lines = if from_end {
+            &lines[lines.len().saturating_sub(count)..]
+        } else {
+            &lines[..count.min(lines.len())]
+        };
+        for line in lines {
+            writeln!(context.stdout, "{}", line)?;
+        }
+    }
+    Ok(CommandResult::Lovely)
+}
+
+pub fn head(context: &mut CommandContext) -> Result<CommandResult> {
+    head_or_tail(context, false)
+}
+
+pub fn tail(context: &mut CommandContext) -> Result<CommandResult> {
+    head_or_tail(context, true)
+}
+
+pub fn wc(context: &mut CommandContext) -> Result<CommandResult> {
+    let (flags, files) = parse_flags(context.args.iter().copied(), &[])?;
+    let mut show_lines = false;
+    let mut show_words = false;
+    let mut show_bytes = false;
+    for (flag, _) in flags {
+        match flag {
+            'l' => show_lines = true,
+            'w' => show_words = true,
+            'c' => show_bytes = true,
+            _ => return Err(unknown_flag(flag)),
+        }
+    }
+    // without flags, show everything
+    if !(show_lines || show_words || show_bytes) {
+        show_lines = true;
+        show_words = true;
+        show_bytes = true;
+    }
+
+    let inputs = read_inputs(context, &files)?;
+    let mut rows: Vec<([usize; 3], Option<String>)> = Vec::new();
+    let mut total = [0; 3];
+    for input in &inputs {
+        let text = input.text();
+        let counts = [
+            text.lines().count(),
+            text.split_whitespace().count(),
+            input.data.len(),
+        ];
+        for (total, count) in total.iter_mut().zip(counts) {
+            *total += count;
+        }
+        let name = (!files.is_empty()).then(|| input.name.clone());
+        rows.push((counts, name));
+    }
+    if inputs.len() > 1 {
+        rows.push((total, Some(String::from("total"))));
+    }
+
+    let shown = [show_lines, show_words, show_bytes];
+    let width = total.iter().max().unwrap_or(&0).to_string().len();
+    for (counts, name) in rows {
+        let mut fields: Vec<String> = counts
+            .iter()
+            .zip(shown)
+            .filter(|(_, shown)| *shown)
+            .map(|(count, _)| format!("{:>width$}", count))
+            .collect();
+        if let Some(name) = name {
+            fields.push(name);
+        }
+        writeln!(context.stdout, "{}", fields.join(" "))?;
+    }
+    Ok(CommandResult::Lovely)
+}
+
+/// Number at the start of a line, used by `sort -n`. Lines without one count as 0
+fn leading_number(line: &str) -> f64 {
+    let line = line.trim_start();
+    let end = line
+        .char_indices()
+        .find(|(index, char)| {
+            !(char.is_ascii_digit() || *char == '.' || (*index == 0 && *char == '-'))
+        })
+        .map(|(index, _)| index)
+        .unwrap_or(line.len());
+    line[..end].parse().unwrap_or(0.0)
+}
+
+pub fn sort(context: &mut CommandContext) -> Result<CommandResult> {
+    let (flags, files) = parse_flags(context.args.iter().copied(), &[])?;
+    let mut numeric = false;
+    let mut reverse = false;
+    for (flag, _) in flags {
+        match flag {
+            'n' => numeric = true,
+            'r' => reverse = true,
+            _ => return Err(unknown_flag(flag)),
+        }
+    }
+
+    let texts: Vec<String> = read_inputs(context, &files)?
+        .iter()
+        .map(|input| input.text())
+        .collect();
+    let mut lines: Vec<&str> = texts.iter().flat_map(|text| text.lines()).collect();
+    lines.sort_by(|a, b| {
+        let ordering = if numeric {
+            leading_number(a)
+                .partial_cmp(&leading_number(b))
+                .unwrap_or(Ordering::Equal)
+                .then_with(|| a.cmp(b))
+        } else {
+            a.cmp(b)
+        };
+        if reverse {
+            ordering.reverse()
+        } else {
+            ordering
+        }
+    });
+    for line in lines {
+        writeln!(context.stdout, "{}", line)?;
+    }
+    Ok(CommandResult::Lovely)
+}
+
+pub fn uniq(context: &mut CommandContext) -> Result<CommandResult> {
+    let (flags, files) = parse_flags(context.args.iter().copied(), &[])?;
+    let mut show_counts = false;
+    for (flag, _) in flags {
+        match flag {
+            'c' => show_counts = true,
+            _ => return Err(unknown_flag(flag)),
+        }
+    }
+
+    // like the unix uniq, only repeated lines next to each other are merged
+    let mut groups: Vec<(&str, usize)> = Vec::new();
+    let inputs = read_inputs(context, &files)?;
+    let texts: Vec<String> = inputs.iter().map(Input::text).collect();
+    for line in texts.iter().flat_map(|text| text.lines()) {
+        match groups.last_mut() {
+            Some((last, count)) if *last == line => *count += 1,
+            _ => groups.push((line, 1)),
+        }
+    }
+    for (line, count) in groups {
+        if show_counts {
+            writeln!(context.stdout, "{:>7} {}", count, line)?;
+        } else {
+            writeln!(context.stdout, "{}", line)?;
+        }
+    }
+    Ok(CommandResult::Lovely)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_flags_until_terminator() {
+        let args = ["-i", "-n", "5", "--", "-v", "file"];
+        let (flags, rest) = parse_flags(args.into_iter(), &['n']).unwrap();
+        assert_eq!(flags, [('i', None), ('n', Some("5"))]);
+        assert_eq!(rest, ["-v", "file"]);
+    }
+}
@@ -0,0 +1,462 @@
+//! The `find` builtin, which searches a directory tree
+//!
+//! Items can be filtered by name (with the same wildcards as other commands), type, size, modification time and depth.
+//! By default, items ignored by `.gitignore` files (and `.git` directories) are skipped.
+
+use std::{
+    fs,
+    io::{Result, Write},
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime},
+};
+
+use crossterm::{
+    queue,
+    style::{Color, SetForegroundColor},
+};
+
+use crate::{
+    commands::{CommandContext, CommandResult},
+    utils::wildcard_match,
+};
+
+/// How a number given to `-size` or `-mtime` is compared
+#[derive(Clone, Copy)]
+enum Comparison {
+    /// Prefixed with +
+    MoreThan,
+    /// Prefixed with -
+    LessThan,
+    Exactly,
+}
+
+impl Comparison {
+    /// Split the comparison prefix off a value, like `+10k`
+    fn parse(value: &str) -> (Self, &str) {
+        if let Some(value) = value.strip_prefix('+') {
+            (Comparison::MoreThan, value)
+        } else if let Some(value) = value.strip_prefix('-') {
+            (Comparison::LessThan, value)
+        } else {
+            (Comparison::Exactly, value)
+        }
+    }
+    /// Compare an amount against a limit, both in the same unit.
+    /// Exactly matches anything that rounds up to the limit, like find does
+    fn matches(self, amount: f64, limit: f64) -> bool {
+        match self {
+            Comparison::MoreThan => amount > limit,
+            Comparison::LessThan => amount < limit,
+            Comparison::Exactly => amount.ceil() == limit,
+        }
+    }
+}
+
+/// A number with a unit suffix, like `10k` or `3h`. The unit is returned as how many of the base unit it is
+fn parse_with_unit(value: &str, units: &[(char, f64)], option: &str) -> Result<(f64, f64)> {
+    let invalid = || std::io::Error::other(format!("Invalid value '{}' for {}", value, option));
+    let (number, unit) = match value.chars().last() {
+        Some(last) if !last.is_ascii_digit() => {
+            let unit = units
+                .iter()
+                .find(|(suffix, _)| *suffix == last)
+                .ok_or_else(invalid)?
+                .1;
+            (&value[..value.len() - last.len_utf8()], unit)
+        }
+        _ => (value, units[0].1),
+    };
+    let number = number.parse().map_err(|_| invalid())?;
+    Ok((number, unit))
+}
+
+/// Size suffixes for `-size`. Plain numbers are bytes
+const SIZE_UNITS: &[(char, f64)] = &[
+    ('c', 1.0),
+    ('k', 1024.0),
+    ('M', 1024.0 * 1024.0),
+    ('G', 1024.0 * 1024.0 * 1024.0),
+];
+/// Time suffixes for `-mtime`, in seconds. Plain numbers are days
+const TIME_UNITS: &[(char, f64)] = &[
+    ('d', 24.0 * 60.0 * 60.0),
+    ('h', 60.0 * 60.0),
+    ('m', 60.0),
+    ('s', 1.0),
+];
+
+#[derive(Default)]
+struct Filters {
+    /// Wildcard patterns, of which the name must match at least one
+    names: Vec<String>,
+    ignore_case: bool,
+    /// Types to include, as `f`, `d` or `l`. Empty means all
+    types: Vec<char>,
+    /// Comparison, amount and unit size in bytes
+    size: Option<(Comparison, f64, f64)>,
+    /// Comparison, amount and unit size in seconds
+    modified: Option<(Comparison, f64, f64)>,
+    min_depth: usize,
+    max_depth: Option<usize>,
+}
+
+impl Filters {
+    fn matches(&self, name: &str, metadata: &fs::Metadata, now: SystemTime) -> bool {
+        if !self.names.is_empty() {
+            let name = if self.ignore_case {
+                name.to_lowercase()
+            } else {
+                name.to_string()
+            };
+            if !self
+                .names
+                .iter()
+                .any(|pattern| wildcard_match(pattern, &name))
+            {
+                return false;
+            }
+        }
+        if !self.types.is_empty() {
+            let file_type = metadata.file_type();
+            let item_type = if file_type.is_symlink() {
+                'l'
+            } else if file_type.is_dir() {
+                'd'
+            } else {
+                'f'
+            };
+            if !self.types.contains(&item_type) {
+                return false;
+            }
+        }
+        if let Some((comparison, limit, unit)) = self.size
+            && !comparison.matches(metadata.len() as f64 / unit, limit)
+        {
+            return false;
+        }
+        if let Some((comparison, limit, unit)) = self.modified {
+            let age = metadata
+                .modified()
+                .ok()
+                .and_then(|modified| now.duration_since(modified).ok())
+                .unwrap_or(Duration::ZERO);
+            if !comparison.matches(age.as_secs_f64() / unit, limit) {
+                return false;
+            }
+        }
+        true
+    }
+}
+
+/// A rule from a .gitignore file
+struct IgnoreRule {
+    pattern: String,
+    /// Starts with `!`, so it un-ignores what it matches
+    negated: bool,
+    /// Ends with `/`, so it only matches directories
+    dir_only: bool,
+    /// Contains a `/`, so it is matched against the path relative to the .gitignore, and not just the name
+    anchored: bool,
+}
+
+impl IgnoreRule {
+    fn parse(line: &str) -> Option<Self> {
+        let line = line.trim_end();
+        if line.is_empty() || line.starts_with('#') {
+            return None;
+        }
+        let (negated, pattern) = match line.strip_prefix('!') {
+            Some(pattern) => (true, pattern),
+            None => (false, line),
+        };
+        let (dir_only, pattern) = match pattern.strip_suffix('/') {
+            Some(pattern) => (true, pattern),
+            None => (false, pattern),
+        };
+        // a leading **/ matches in any directory, same as no slash at all, unless there are more slashes
+        let pattern = match pattern.strip_prefix("**/") {
+            Some(rest) if !rest.contains('/') => rest,
+            _ => pattern,
+        };
+        let anchored = pattern.contains('/');
+        let pattern = pattern.strip_prefix('/').unwrap_or(pattern);
+        Some(IgnoreRule {
+            pattern: pattern.to_string(),
+            negated,
+            dir_only,
+            anchored,
+        })
+    }
+}
+
+/// The rules of a .gitignore file, and the directory it is in
+struct GitIgnore {
+    dir: PathBuf,
+    rules: Vec<IgnoreRule>,
+}
+
+impl GitIgnore {
+    fn load(dir: &Path) -> Option<Self> {
+        let text = fs::read_to_string(dir.join(".gitignore")).ok()?;
+        Some(GitIgnore {
+            dir: dir.to_path_buf(),
+            rules: text.lines().filter_map(IgnoreRule::parse).collect(),
+        })
+    }
+}
+
+/// Match a pattern against a path, one `/` seperated segment at a time, like git does.
+/// `*` and `?` don't match across segments, and `**` matches any amount of whole segments
+fn match_segments(pattern: &[&str], path: &[&str]) -> bool {
+    match pattern.split_first() {
+        None => path.is_empty(),
+        // a trailing ** matches everything inside, but not the directory itself
+        Some((&"**", [])) => !path.is_empty(),
+        Some((&"**", rest)) => (0..=path.len()).any(|skip| match_segments(rest, &path[skip..])),
+        Some((segment, rest)) => match path.split_first() {
+            Some((name, path_rest)) => {
+                wildcard_match(segment, name) && match_segments(rest, path_rest)
+            }
+            None => false,
+        },
+    }
+}
+
+struct Walker {
+    filters: Filters,
+    respect_ignores: bool,
+    /// .gitignore files of the directories being walked, outermost first
+    ignores: Vec<GitIgnore>,
+    now: SystemTime,
+    /// Found items, and whether they are directories
+    found: Vec<(PathBuf, bool)>,
+}
+
+impl Walker {
+    /// Load .gitignore files of the directories above where the search starts, up to the root of the git repo
+    fn load_parent_ignores(&mut self, start: &Path) {
+        let parents: Vec<&Path> = start.ancestors().skip(1).collect();
+        let Some(root) = parents.iter().position(|dir| dir.join(".git").exists()) else {
+            return;
+        };
+        for dir in parents[..=root].iter().rev() {
+            self.ignores.extend(GitIgnore::load(dir));
+        }
+    }
+
+    /// Whether a path is ignored. The last matching rule decides, so later rules can un-ignore things
+    fn is_ignored(&self, path: &Path, is_dir: bool) -> bool {
+        let name = path.file_name().unwrap_or_default().to_string_lossy();
+        let mut ignored = false;
+        for ignore in &self.ignores {
+            let Ok(relative) = path.strip_prefix(&ignore.dir) else {
+                continue;
+            };
+            let relative = relative.to_string_lossy().replace('\\', "/");
+            for rule in &ignore.rules {
+                if rule.dir_only && !is_dir {
+                    continue;
+                }
+                let matches = if rule.anchored {
+                    let pattern: Vec<&str> = rule.pattern.split('/').collect();
+                    let path: Vec<&str> = relative.split('/').collect();
+                    match_segments(&pattern, &path)
+                } else {
+                    wildcard_match(&rule.pattern, &name)
+                };
+                if matches {
+                    ignored = !rule.negated;
+                }
+            }
+        }
+        ignored
+    }
+
+    fn walk(&mut self, dir: &Path, depth: usize) {
+        let loaded_ignore = self.respect_ignores
+            && match GitIgnore::load(dir) {
+                Some(ignore) => {
+                    self.ignores.push(ignore);
+                    true
+                }
+                None => false,
+            };
+
+        // directories that can't be read are skipped
+        let mut entries: Vec<fs::DirEntry> = match fs::read_dir(dir) {
+            Ok(entries) => entries.flatten().collect(),
+            Err(_) => Vec::new(),
+        };
+        entries.sort_by_key(|entry| entry.file_name());
+
+        for entry in entries {
+            let path = entry.path();
+            let Ok(metadata) = fs::symlink_metadata(&path) else {
+                continue;
+            };
+            let is_dir = metadata.is_dir();
+            let name = entry.file_name().to_string_lossy().to_string();
+            if self.respect_ignores && (name == ".git" || self.is_ignored(&path, is_dir)) {
+                continue;
+            }
+            let item_depth = depth + 1;
+            if item_depth >= self.filters.min_depth
+                && self.filters.matches(&name, &metadata, self.now)
+            {
+                self.found.push((path.clone(), is_dir));
+            }
+            if is_dir
+                && self
+                    .filters
+                    .max_depth
+                    .is_none_or(|max_depth| item_depth < max_depth)
+            {
+                self.walk(&path, item_depth);
+            }
+        }
+
+        if loaded_ignore {
+            self.ignores.pop();
+        }
+    }
+}
+
+/// Quote an argument so shoe parses it back as the exact same text
+fn quote_arg(arg: &str) -> String {
+    let mut quoted = String::from('"');
+    for char in arg.chars() {
+        if matches!(char, '\\' | '"' | '%') {
+            quoted.push('\\');
+        }
+        quoted.push(char);
+    }
+    quoted.push('"');
+    quoted
+}
+
+pub fn find(context: &mut CommandContext) -> Result<CommandResult> {
+    let mut filters = Filters {
+        min_depth: 1,
+        ..Default::default()
+    };
+    let mut dir: Option<&str> = None;
+    let mut respect_ignores = true;
+    let mut exec: Option<Vec<&str>> = None;
+
+    let mut args = context.args.iter().copied();
+    while let Some(arg) = args.next() {
+        let mut value = || {
+            args.next()
+                .ok_or_else(|| std::io::Error::other(format!("{} needs a value", arg)))
+        };
+        match arg {
+            "-name" => filters.names.push(value()?.to_string()),
+            "-iname" => {
+                filters.names.push(value()?.to_lowercase());
+                filters.ignore_case = true;
+            }
+            "-type" => {
+                for item_type in value()?.split(',') {
+                    match item_type {
+                        "f" | "d" | "l" => filters.types.extend(item_type.chars()),
+                        _ => {
+                            let message = format!("Unknown type '{}', use f, d or l", item_type);
+                            return Err(std::io::Error::other(message));
+                        }
+                    }
+                }
+            }
+            "-size" => {
+                let (comparison, size) = Comparison::parse(value()?);
+                let (amount, unit) = parse_with_unit(size, SIZE_UNITS, arg)?;
+                filters.size = Some((comparison, amount, unit));
+            }
+            "-mtime" => {
+                let (comparison, age) = Comparison::parse(value()?);
+                let (amount, unit) = parse_with_unit(age, TIME_UNITS, arg)?;
+                filters.modified = Some((comparison, amount, unit));
+            }
+            "-mindepth" | "-maxdepth" => {
+                let depth = value()?;
+                let depth: usize = depth.parse().map_err(|_| {
+                    std::io::Error::other(format!("Invalid value '{}' for {}", depth, arg))
+                })?;
+                if arg == "-mindepth" {
+                    filters.min_depth = depth;
+                } else {
+                    filters.max_depth = Some(depth);
+                }
+            }
+            "--no-ignore" => respect_ignores = false,
+            "-exec" => {
+                // everything after -exec is the command to run
+                exec = Some(args.by_ref().collect());
+            }
+            _ if !arg.starts_with('-') && dir.is_none() => dir = Some(arg),
+            _ => return Err(std::io::Error::other(format!("Unknown option '{}'", arg))),
+        }
+    }
+
+    let start = std::path::absolute(dir.unwrap_or("."))?;
+    if !start.is_dir() {
+        let message = format!("'{}' is not a directory", dir.unwrap_or("."));
+        return Err(std::io::Error::other(message));
+    }
+    let mut walker = Walker {
+        filters,
+        respect_ignores,
+        ignores: Vec::new(),
+        now: SystemTime::now(),
+        found: Vec::new(),
+    };
+    if respect_ignores {
+        walker.load_parent_ignores(&start);
+    }
+    walker.walk(&start, 0);
+
+    // show paths relative to the given dir, or to the cwd if none was given
+    let found = walker.found.into_iter().map(|(path, is_dir)| {
+        let relative = path.strip_prefix(&start).unwrap_or(&path);
+        let path = match dir {
+            Some(dir) => Path::new(dir).join(relative),
+            None => relative.to_path_buf(),
+        };
+        (path.to_string_lossy().to_string(), is_dir)
+    });
+
+    if let Some(exec) = exec {
+        let Some((keyword, exec_args)) = exec.split_first() else {
+            return Err(std::io::Error::other("-exec needs a command"));
+        };
+        // {} in the args is replaced by the path, otherwise the path is added at the end
+        let has_placeholder = exec_args.contains(&"{}");
+        let commands = found
+            .map(|(path, _)| {
+                let mut command = vec![keyword.to_string()];
+                for arg in exec_args {
+                    let arg = if *arg == "{}" { &path } else { *arg };
+                    command.push(quote_arg(arg));
+                }
+                if !has_placeholder {
+                    command.push(quote_arg(&path));
+                }
+                command.join(" ")
+            })
+            .collect();
+        return Ok(CommandResult::RunCommands(commands));
+    }
+
+    for (path, is_dir) in found {
+        if is_dir && context.output_is_terminal {
+            queue!(
+                context.stdout,
+                SetForegroundColor(context.theme.primary_color)
+            )?;
+            writeln!(context.stdout, "{}", path)?;
+            queue!(context.stdout, SetForegroundColor(Color::Reset))?;
+        } else {
+            writeln!(context.stdout, "{}", path)?;
+        }
+    }
+    Ok(CommandResult::Lovely)
+}
@@ -0,0 +1,194 @@
+//! Directories visited with `cd`, ranked by "frecency" (how often and how recently they were visited), for jumping to with `z`
+//!
+//! Stored at ~/.shoez, next to the history file. Each directory is on its own line, as tab seperated fields: visit count, time of last visit and the path.
+//! Like the history file, it is locked while reading and writing, so several sessions can share it.
+
+use std::{
+    fs::{self, OpenOptions},
+    io::{self, Read, Seek, SeekFrom, Write},
+    path::Path,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+/// When the visit counts of all directories add up to more than this, they are all scaled down, so old favourites fade away
+const MAX_TOTAL_VISITS: f64 = 5000.0;
+/// How much visit counts are scaled by when aging
+const AGING_FACTOR: f64 = 0.9;
+
+pub struct VisitedDirectory {
+    pub path: String,
+    /// Amount of visits. Not a whole number, since visits are scaled down over time
+    pub visits: f64,
+    /// Unix timestamp (in seconds) of the last visit
+    pub last_visit: u64,
+}
+
+impl VisitedDirectory {
+    /// Frecency of the directory. Visits count for more if the directory was visited recently
+    pub fn score(&self, now: u64) -> f64 {
+        let age = now.saturating_sub(self.last_visit);
+        let weight = match age {
+            0..3600 => 4.0,
+            3600..86400 => 2.0,
+            86400..604800 => 0.5,
+            _ => 0.25,
+        };
+        self.visits * weight
+    }
+    fn to_line(&self) -> String {
+        format!("{}\t{}\t{}", self.visits, self.last_visit, self.path)
+    }
+    fn from_line(line: &str) -> Option<Self> {
+        let mut fields = line.splitn(3, '\t');
+        let visits = fields.next()?.parse().ok()?;
+        let last_visit = fields.next()?.parse().ok()?;
+        let path = fields.next()?.to_string();
+        Some(VisitedDirectory {
+            path,
+            visits,
+            last_visit,
+        })
+    }
+}
+
+pub fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or_default()
+}
+
+pub struct DirectoryDatabase {
+    path: String,
+    pub directories: Vec<VisitedDirectory>,
+}
+
+impl DirectoryDatabase {
+    /// Open the database file, creating it if it doesn't exist
+    pub fn open(path: String) -> io::Result<Self> {
+        let mut database = DirectoryDatabase {
+            path,
+            directories: Vec::new(),
+        };
+        let mut file = database.open_file()?;
+        file.lock_shared()?;
+        let result = read_directories(&mut file);
+        file.unlock()?;
+        database.directories = result?;
+        Ok(database)
+    }
+
+    fn open_file(&self) -> io::Result<fs::File> {
+        OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(&self.path)
+    }
+
+    /// Record a visit to a directory.
+    ///
+    /// The file is read again first, so visits recorded by other sessions aren't lost.
+    pub fn record(&mut self, directory: &str) -> io::Result<()> {
+        let mut file = self.open_file()?;
+        file.lock()?;
+        let result = read_directories(&mut file).and_then(|directories| {
+            self.directories = directories;
+            let now = now();
+            match self
+                .directories
+                .iter_mut()
+                .find(|visited| visited.path == directory)
+            {
+                Some(visited) => {
+                    visited.visits += 1.0;
+                    visited.last_visit = now;
+                }
+                None => self.directories.push(VisitedDirectory {
+                    path: directory.to_string(),
+                    visits: 1.0,
+                    last_visit: now,
+                }),
+            }
+            self.age();
+
+            let mut text = String::new();
+            for visited in &self.directories {
+                text += &visited.to_line();
+                text.push('\n');
+            }
+            file.set_len(0)?;
+            file.seek(SeekFrom::Start(0))?;
+            file.write_all(text.as_bytes())
+        });
+        file.unlock()?;
+        result
+    }
+
+    /// Scale down all visit counts if there are too many, forgetting directories that haven't been visited in a long time
+    fn age(&mut self) {
+        let total: f64 = self.directories.iter().map(|visited| visited.visits).sum();
+        if total <= MAX_TOTAL_VISITS {
+            return;
+        }
+        for visited in &mut self.directories {
+            visited.visits *= AGING_FACTOR;
+        }
+        self.directories.retain(|visited| visited.visits >= 1.0);
+    }
+}
+
+fn read_directories(file: &mut fs::File) -> io::Result<Vec<VisitedDirectory>> {
+    let mut text = String::new();
+    file.seek(SeekFrom::Start(0))?;
+    file.read_to_string(&mut text)?;
+    Ok(text
+        .lines()
+        .filter_map(VisitedDirectory::from_line)
+        .collect())
+}
+
+/// Whether a path matches the fragments given to `z`.
+///
+/// The fragments must appear in the path in order (ignoring case), and the last one must be in the last part of the path,
+/// so `z proj` goes to `~/projects` rather than `~/projects/shoe/src`.
+fn matches_fragments(path: &str, fragments: &[String]) -> bool {
+    let path = path.to_lowercase();
+    let last_part_start = path.rfind(['/', '\\']).map_or(0, |index| index + 1);
+    let mut position = 0;
+    for (index, fragment) in fragments.iter().enumerate() {
+        let Some(found) = path[position..].find(fragment.as_str()) else {
+            return false;
+        };
+        position += found;
+        let is_last = index + 1 == fragments.len();
+        if is_last && position + fragment.len() <= last_part_start {
+            // the last fragment may appear again in the last part
+            return path[last_part_start..].contains(fragment.as_str());
+        }
+        position += fragment.len();
+    }
+    true
+}
+
+/// Directories matching the fragments, best first. Directories that no longer exist, and the cwd, are left out
+pub fn find_matches<'a>(
+    directories: &'a [VisitedDirectory],
+    fragments: &[&str],
+) -> Vec<&'a VisitedDirectory> {
+    let fragments: Vec<String> = fragments
+        .iter()
+        .map(|fragment| fragment.to_lowercase())
+        .collect();
+    let cwd = std::env::current_dir().ok();
+    let now = now();
+    let mut matches: Vec<&VisitedDirectory> = directories
+        .iter()
+        .filter(|visited| matches_fragments(&visited.path, &fragments))
+        .filter(|visited| cwd.as_deref() != Some(Path::new(&visited.path)))
+        .filter(|visited| Path::new(&visited.path).is_dir())
+        .collect();
+    matches.sort_by(|a, b| b.score(now).total_cmp(&a.score(now)));
+    matches
+}
@@ -0,0 +1,1097 @@
+//! Reading the status of git repos for the prompt, by parsing the files in `.git` directly instead of running `git`
+//!
+//! Reads HEAD, refs (loose and packed), the branch's upstream from the config, commits and trees (loose and packed objects), and the index.
+//! From those, works out how far ahead/behind the upstream the branch is, whether there are staged changes,
+//! and whether tracked files were changed in the work tree. Untracked files aren't looked for, since that means walking the whole work tree.
+//!
+//! Results that are slow to work out are cached per repo, and only worked out again when HEAD, the upstream or the index changes.
+//! Whether the work tree has changes is also worked out again every couple of seconds.
+
+use std::{
+    collections::{BinaryHeap, HashMap},
+    fs,
+    io::{Read, Seek, SeekFrom},
+    path::{Path, PathBuf},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+type ObjectId = [u8; 20];
+
+/// Stop walking commits after this many, so huge histories can't make the prompt slow
+const MAX_COMMITS_WALKED: usize = 10000;
+/// How long to reuse whether the work tree has changes, as long as the index hasn't changed.
+/// Working it out means checking every tracked file, which is too slow to do every time the prompt is shown in big repos
+const UNSTAGED_CACHE_TIME: Duration = Duration::from_secs(2);
+
+const OBJECT_COMMIT: u8 = 1;
+const OBJECT_TREE: u8 = 2;
+const OBJECT_OFS_DELTA: u8 = 6;
+const OBJECT_REF_DELTA: u8 = 7;
+
+/// Git file mode of submodules
+const MODE_GITLINK: u32 = 0o160000;
+const MODE_SYMLINK: u32 = 0o120000;
+
+fn parse_hex(hex: &str) -> Option<ObjectId> {
+    let hex = hex.trim();
+    if hex.len() != 40 {
+        return None;
+    }
+    let mut id = [0; 20];
+    for (index, byte) in id.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(hex.get(index * 2..index * 2 + 2)?, 16).ok()?;
+    }
+    Some(id)
+}
+
+fn to_hex(id: &ObjectId) -> String {
+    id.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+/// What is shown in the prompt for a repo
+pub struct GitStatus {
+    /// Name of the checked out branch, or the start of the commit hash if HEAD is detached
+    pub branch: String,
+    /// Commits on the branch that aren't on its upstream
+    pub ahead: usize,
+    /// Commits on the upstream that aren't on the branch
+    pub behind: usize,
+    /// Whether the index differs from HEAD
+    pub staged: bool,
+    /// Whether tracked files in the work tree differ from the index
+    pub dirty: bool,
+}
+
+impl GitStatus {
+    /// Text for the prompt, like `main ↑1↓2 +*`, where `+` means staged changes and `*` means unstaged changes
+    pub fn segment(&self) -> String {
+        let mut segment = self.branch.clone();
+        if self.ahead > 0 || self.behind > 0 {
+            segment.push(' ');
+            if self.ahead > 0 {
+                segment += &format!("↑{}", self.ahead);
+            }
+            if self.behind > 0 {
+                segment += &format!("↓{}", self.behind);
+            }
+        }
+        if self.staged || self.dirty {
+            segment.push(' ');
+            if self.staged {
+                segment.push('+');
+            }
+            if self.dirty {
+                segment.push('*');
+            }
+        }
+        segment
+    }
+}
+
+enum Head {
+    Branch(String),
+    Detached(ObjectId),
+}
+
+struct Repo {
+    work_tree: PathBuf,
+    git_dir: PathBuf,
+    /// Where shared files (objects, refs and config) are. Differs from git_dir in worktrees
+    common_dir: PathBuf,
+}
+
+impl Repo {
+    /// Find the repo a directory is in, by looking for `.git` in it and its parents.
+    ///
+    /// `.git` can also be a file pointing to the real git directory, like in worktrees and submodules.
+    fn find(start: &Path) -> Option<Self> {
+        for dir in start.ancestors() {
+            let dot_git = dir.join(".git");
+            let git_dir = if dot_git.is_dir() {
+                dot_git
+            } else if dot_git.is_file() {
+                let text = fs::read_to_string(&dot_git).ok()?;
+                dir.join(text.trim().strip_prefix("gitdir:")?.trim())
+            } else {
+                continue;
+            };
+            let common_dir = match fs::read_to_string(git_dir.join("commondir")) {
+                Ok(common_dir) => git_dir.join(common_dir.trim()),
+                Err(_) => git_dir.clone(),
+            };
+            return Some(Repo {
+                work_tree: dir.to_path_buf(),
+                git_dir,
+                common_dir,
+            });
+        }
+        None
+    }
+
+    fn head(&self) -> Option<Head> {
+        let head = fs::read_to_string(self.git_dir.join("HEAD")).ok()?;
+        match head.trim().strip_prefix("ref:") {
+            Some(reference) => Some(Head::Branch(reference.trim().to_string())),
+            None => parse_hex(&head).map(Head::Detached),
+        }
+    }
+
+    /// Find the commit a ref (like `refs/heads/main`) points to, following symbolic refs
+    fn resolve_ref(&self, name: &str) -> Option<ObjectId> {
+        let mut name = name.to_string();
+        // symbolic refs can point to other symbolic refs, but not forever
+        for _ in 0..5 {
+            let loose = [&self.git_dir, &self.common_dir]
+                .iter()
+                .find_map(|dir| fs::read_to_string(dir.join(&name)).ok());
+            match loose {
+                Some(text) => match text.trim().strip_prefix("ref:") {
+                    Some(target) => name = target.trim().to_string(),
+                    None => return parse_hex(&text),
+                },
+                None => return self.packed_ref(&name),
+            }
+        }
+        None
+    }
+
+    fn packed_ref(&self, name: &str) -> Option<ObjectId> {
+        let packed = fs::read_to_string(self.common_dir.join("packed-refs")).ok()?;
+        packed
+            .lines()
+            .filter(|line| !line.starts_with(['#', '^']))
+            .find_map(|line| {
+                let (id, reference) = line.split_once(' ')?;
+                if reference.trim() == name {
+                    parse_hex(id)
+                } else {
+                    None
+                }
+            })
+    }
+
+    /// The ref of a branch's upstream, from the `[branch "name"]` section of the config
+    fn upstream_ref(&self, branch: &str) -> Option<String> {
+        let config = fs::read_to_string(self.common_dir.join("config")).ok()?;
+        let section = format!("branch \"{}\"", branch);
+        let merge = config_value(&config, &section, "merge")?;
+        let remote = config_value(&config, &section, "remote")?;
+        // a remote of . means the upstream is a local branch
+        if remote == "." {
+            return Some(merge);
+        }
+        let merge_branch = merge.strip_prefix("refs/heads/").unwrap_or(&merge);
+        Some(format!("refs/remotes/{}/{}", remote, merge_branch))
+    }
+
+    /// Whether git may change line endings of files when adding them, so work tree files can differ from what's in the index
+    /// without being changed. That is when `core.autocrlf` is set (in the repo's or the user's config), or attributes could ask for it
+    fn converts_line_endings(&self) -> bool {
+        if self.work_tree.join(".gitattributes").exists() {
+            return true;
+        }
+        // the repo's config overrides the user's. the system config isn't read
+        let configs = [
+            self.common_dir.join("config"),
+            PathBuf::from(shellexpand::tilde("~/.gitconfig").to_string()),
+            PathBuf::from(shellexpand::tilde("~/.config/git/config").to_string()),
+        ];
+        let autocrlf = configs.iter().find_map(|path| {
+            let config = fs::read_to_string(path).ok()?;
+            config_value(&config, "core", "autocrlf")
+        });
+        autocrlf.is_some_and(|value| matches!(value.to_lowercase().as_str(), "true" | "input"))
+    }
+}
+
+/// Find the value of a key in a section of a git config file, like `remote` in `[branch "main"]`. The last one wins, like in git
+fn config_value(config: &str, wanted_section: &str, wanted_key: &str) -> Option<String> {
+    let mut in_section = false;
+    let mut found = None;
+    for line in config.lines() {
+        let line = line.trim();
+        if let Some(section) = line.strip_prefix('[') {
+            in_section = section.trim_end_matches(']').trim() == wanted_section;
+            continue;
+        }
+        if !in_section {
+            continue;
+        }
+        let Some((key, value)) = line.split_once('=') else {
+            continue;
+        };
+        if key.trim().eq_ignore_ascii_case(wanted_key) {
+            found = Some(value.trim().to_string());
+        }
+    }
+    found
+}
+
+/// A pack of objects, found by looking them up in its index
+struct Pack {
+    index: Vec<u8>,
+    count: usize,
+    path: PathBuf,
+}
+
+impl Pack {
+    fn open(index_path: &Path) -> Option<Self> {
+        let index = fs::read(index_path).ok()?;
+        // only version 2 indexes are supported, which git has written by default for a long time
+        if index.get(0..8)? != [0xff, b't', b'O', b'c', 0, 0, 0, 2] {
+            return None;
+        }
+        let count = read_u32(&index, 8 + 255 * 4)? as usize;
+        Some(Pack {
+            index,
+            count,
+            path: index_path.with_extension("pack"),
+        })
+    }
+
+    /// Offset of an object in the pack file
+    fn find_offset(&self, id: &ObjectId) -> Option<u64> {
+        let names_start = 8 + 256 * 4;
+        let name_at = |index: usize| {
+            let start = names_start + index * 20;
+            self.index.get(start..start + 20)
+        };
+        // the fanout table says how many objects start with a byte less than or equal to each value
+        let first_byte = id[0] as usize;
+        let mut low = match first_byte {
+            0 => 0,
+            _ => read_u32(&self.index, 8 + (first_byte - 1) * 4)? as usize,
+        };
+        let mut high = read_u32(&self.index, 8 + first_byte * 4)? as usize;
+        while low < high {
+            let middle = (low + high) / 2;
+            match name_at(middle)?.cmp(id.as_slice()) {
+                std::cmp::Ordering::Less => low = middle + 1,
+                std::cmp::Ordering::Greater => high = middle,
+                std::cmp::Ordering::Equal => {
+                    let offsets_start = names_start + self.count * 24;
+                    let offset = read_u32(&self.index, offsets_start + middle * 4)?;
+                    // the top bit means the offset is in the table of large offsets
+                    if offset & 0x8000_0000 == 0 {
+                        return Some(offset as u64);
+                    }
+                    let large_start = offsets_start + self.count * 4;
+                    let large_index = (offset & 0x7fff_ffff) as usize;
+                    let bytes = self.index.get(large_start + large_index * 8..)?.get(..8)?;
+                    return Some(u64::from_be_bytes(bytes.try_into().ok()?));
+                }
+            }
+        }
+        None
+    }
+}
+
+fn read_u32(data: &[u8], at: usize) -> Option<u32> {
+    Some(u32::from_be_bytes(data.get(at..at + 4)?.try_into().ok()?))
+}
+
+/// Reads objects, from loose object files or packs
+struct ObjectStore {
+    objects_dir: PathBuf,
+    packs: Vec<Pack>,
+}
+
+impl ObjectStore {
+    fn open(repo: &Repo) -> Self {
+        let objects_dir = repo.common_dir.join("objects");
+        let packs = fs::read_dir(objects_dir.join("pack"))
+            .map(|entries| {
+                entries
+                    .flatten()
+                    .map(|entry| entry.path())
+                    .filter(|path| path.extension().is_some_and(|extension| extension == "idx"))
+                    .filter_map(|path| Pack::open(&path))
+                    .collect()
+            })
+            .unwrap_or_default();
+        ObjectStore { objects_dir, packs }
+    }
+
+    /// Read an object, returning its type and contents
+    fn read(&self, id: &ObjectId) -> Option<(u8, Vec<u8>)> {
+        let hex = to_hex(id);
+        if let Ok(compressed) = fs::read(self.objects_dir.join(&hex[..2]).join(&hex[2..])) {
+            let data = miniz_oxide::inflate::decompress_to_vec_zlib(&compressed).ok()?;
+            let header_end = data.iter().position(|byte| *byte == 0)?;
+            let object_type = match data.get(..header_end)?.split(|byte| *byte == b' ').next()? {
+                b"commit" => OBJECT_COMMIT,
+                b"tree" => OBJECT_TREE,
+                _ => 0,
+            };
+            return Some((object_type, data[header_end + 1..].to_vec()));
+        }
+        self.packs.iter().find_map(|pack| {
+            let offset = pack.find_offset(id)?;
+            let mut file = fs::File::open(&pack.path).ok()?;
+            self.read_packed(&mut file, offset, 0)
+        })
+    }
+
+    fn read_packed(&self, file: &mut fs::File, offset: u64, depth: usize) -> Option<(u8, Vec<u8>)> {
+        // delta chains are limited in length by git, so this only guards against broken packs
+        if depth > 64 {
+            return None;
+        }
+        // the header is a type and size, and then either the offset or id of the base object for deltas
+        let mut header = [0; 32];
+        file.seek(SeekFrom::Start(offset)).ok()?;
+        let header_len = file.read(&mut header).ok()?;
+        let header = &header[..header_len];
+
+        let mut position = 0;
+        let mut byte = *header.get(position)?;
+        position += 1;
+        let object_type = (byte >> 4) & 7;
+        let mut size = (byte & 0x0f) as u64;
+        let mut shift = 4;
+        while byte & 0x80 != 0 {
+            byte = *header.get(position)?;
+            position += 1;
+            size |= ((byte & 0x7f) as u64) << shift;
+            shift += 7;
+        }
+
+        let mut base_offset = None;
+        let mut base_id = None;
+        if object_type == OBJECT_OFS_DELTA {
+            let mut byte = *header.get(position)?;
+            position += 1;
+            let mut distance = (byte & 0x7f) as u64;
+            while byte & 0x80 != 0 {
+                byte = *header.get(position)?;
+                position += 1;
+                distance = ((distance + 1) << 7) | (byte & 0x7f) as u64;
+            }
+            base_offset = Some(offset.checked_sub(distance)?);
+        } else if object_type == OBJECT_REF_DELTA {
+            base_id = Some(ObjectId::try_from(header.get(position..position + 20)?).ok()?);
+            position += 20;
+        }
+
+        // compressed data is at most a little bigger than the uncompressed data
+        let mut compressed = Vec::new();
+        file.seek(SeekFrom::Start(offset + position as u64)).ok()?;
+        file.take(size + size / 100 + 1024)
+            .read_to_end(&mut compressed)
+            .ok()?;
+        let data = miniz_oxide::inflate::decompress_to_vec_zlib(&compressed).ok()?;
+
+        let (base_type, base) = match (base_offset, base_id) {
+            (Some(base_offset), _) => self.read_packed(file, base_offset, depth + 1)?,
+            (_, Some(base_id)) => self.read(&base_id)?,
+            _ => return Some((object_type, data)),
+        };
+        Some((base_type, apply_delta(&base, &data)?))
+    }
+
+    fn read_commit(&self, id: &ObjectId) -> Option<Commit> {
+        let (object_type, data) = self.read(id)?;
+        if object_type != OBJECT_COMMIT {
+            return None;
+        }
+        let text = String::from_utf8_lossy(&data);
+        let mut commit = Commit {
+            tree: None,
+            parents: Vec::new(),
+            time: 0,
+        };
+        // the headers end at the first empty line
+        for line in text.lines().take_while(|line| !line.is_empty()) {
+            if let Some(tree) = line.strip_prefix("tree ") {
+                commit.tree = parse_hex(tree);
+            } else if let Some(parent) = line.strip_prefix("parent ") {
+                commit.parents.extend(parse_hex(parent));
+            } else if let Some(committer) = line.strip_prefix("committer ") {
+                // the time is the second to last field, before the timezone
+                commit.time = committer
+                    .rsplit(' ')
+                    .nth(1)
+                    .and_then(|time| time.parse().ok())
+                    .unwrap_or(0);
+            }
+        }
+        Some(commit)
+    }
+
+    /// Add every file in a tree (and its subtrees) to `files`, by path
+    fn flatten_tree(
+        &self,
+        id: &ObjectId,
+        prefix: &str,
+        files: &mut HashMap<String, ObjectId>,
+    ) -> Option<()> {
+        let (object_type, data) = self.read(id)?;
+        if object_type != OBJECT_TREE {
+            return None;
+        }
+        // entries are "<mode> <name>\0<20 byte id>"
+        let mut position = 0;
+        while position < data.len() {
+            let space = position + data[position..].iter().position(|byte| *byte == b' ')?;
+            let nul = space + data[space..].iter().position(|byte| *byte == 0)?;
+            let mode = std::str::from_utf8(&data[position..space]).ok()?;
+            let name = String::from_utf8_lossy(&data[space + 1..nul]);
+            let entry_id = ObjectId::try_from(data.get(nul + 1..nul + 21)?).ok()?;
+            let path = format!("{}{}", prefix, name);
+            if mode == "40000" {
+                self.flatten_tree(&entry_id, &format!("{}/", path), files)?;
+            } else {
+                files.insert(path, entry_id);
+            }
+            position = nul + 21;
+        }
+        Some(())
+    }
+}
+
+fn read_varint(data: &[u8], position: &mut usize) -> Option<usize> {
+    let mut value = 0;
+    let mut shift = 0;
+    loop {
+        let byte = *data.get(*position)?;
+        *position += 1;
+        value |= ((byte & 0x7f) as usize) << shift;
+        shift += 7;
+        if byte & 0x80 == 0 {
+            return Some(value);
+        }
+    }
+}
+
+/// Build an object from a base object and a delta, which is a list of instructions to copy parts of the base or insert new data
+fn apply_delta(base: &[u8], delta: &[u8]) -> Option<Vec<u8>> {
+    let mut position = 0;
+    let _base_size = read_varint(delta, &mut position)?;
+    let result_size = read_varint(delta, &mut position)?;
+    // the size comes from the file, so don't trust it with a huge allocation. results are rarely much bigger than their parts
+    let mut result = Vec::with_capacity(result_size.min(base.len() + delta.len()));
+    while position < delta.len() {
+        let instruction = delta[position];
+        position += 1;
+        if instruction & 0x80 != 0 {
+            // copy from base. the low bits say which bytes of the offset and size follow
+            let mut offset = 0;
+            let mut size = 0;
+            for index in 0..4 {
+                if instruction & (1 << index) != 0 {
+                    offset |= (*delta.get(position)? as usize) << (8 * index);
+                    position += 1;
+                }
+            }
+            for index in 0..3 {
+                if instruction & (0x10 << index) != 0 {
+                    size |= (*delta.get(position)? as usize) << (8 * index);
+                    position += 1;
+                }
+            }
+            if size == 0 {
+                size = 0x10000;
+            }
+            result.extend_from_slice(base.get(offset..offset + size)?);
+        } else if instruction != 0 {
+            // insert the next bytes
+            let size = instruction as usize;
+            result.extend_from_slice(delta.get(position..position + size)?);
+            position += size;
+        } else {
+            return None;
+        }
+    }
+    (result.len() == result_size).then_some(result)
+}
+
+struct Commit {
+    tree: Option<ObjectId>,
+    parents: Vec<ObjectId>,
+    /// Commit time, as a unix timestamp
+    time: i64,
+}
+
+const FROM_LOCAL: u8 = 1;
+const FROM_UPSTREAM: u8 = 2;
+const FROM_BOTH: u8 = FROM_LOCAL | FROM_UPSTREAM;
+
+/// Commits waiting to be walked by [ahead_behind], newest first
+struct CommitQueue<'a> {
+    store: &'a ObjectStore,
+    heap: BinaryHeap<(i64, ObjectId)>,
+    /// Which of the two commits each commit is reachable from
+    flags: HashMap<ObjectId, u8>,
+    /// Commits are only read once, when first queued
+    commits: HashMap<ObjectId, Option<Commit>>,
+    /// How many times each commit is in the queue
+    queued: HashMap<ObjectId, usize>,
+    /// How many commits in the queue aren't known to be reachable from both yet
+    one_sided: usize,
+}
+
+impl CommitQueue<'_> {
+    /// Mark a commit as reachable from `flag`, and queue it if that is new
+    fn add(&mut self, id: ObjectId, flag: u8) {
+        let old_flag = self.flags.get(&id).copied().unwrap_or_default();
+        let new_flag = old_flag | flag;
+        if new_flag == old_flag {
+            return;
+        }
+        self.flags.insert(id, new_flag);
+        let queued = self.queued.entry(id).or_default();
+        // copies of it already in the queue are now reachable from both too
+        if new_flag == FROM_BOTH {
+            self.one_sided -= *queued;
+        } else {
+            self.one_sided += 1;
+        }
+        *queued += 1;
+
+        let store = self.store;
+        let time = self
+            .commits
+            .entry(id)
+            .or_insert_with(|| store.read_commit(&id))
+            .as_ref()
+            .map_or(0, |commit| commit.time);
+        self.heap.push((time, id));
+    }
+
+    /// Take the newest commit, with its time and what it is reachable from
+    fn pop(&mut self) -> Option<(i64, ObjectId, u8)> {
+        let (time, id) = self.heap.pop()?;
+        let flag = self.flags[&id];
+        if let Some(queued) = self.queued.get_mut(&id) {
+            *queued -= 1;
+        }
+        if flag != FROM_BOTH {
+            self.one_sided -= 1;
+        }
+        Some((time, id, flag))
+    }
+
+    /// Time of the oldest commit found so far that is only reachable from one of the two
+    fn oldest_one_sided(&self) -> Option<i64> {
+        self.flags
+            .iter()
+            .filter(|(_, flag)| **flag != FROM_BOTH)
+            .map(|(id, _)| {
+                self.commits
+                    .get(id)
+                    .and_then(Option::as_ref)
+                    .map_or(0, |commit| commit.time)
+            })
+            .min()
+    }
+}
+
+/// Count the commits only reachable from `local` (ahead) and only reachable from `upstream` (behind).
+///
+/// Walks both histories at once, newest commits first, until only commits reachable from both are left,
+/// and they are older than every commit reachable from only one.
+fn ahead_behind(store: &ObjectStore, local: ObjectId, upstream: ObjectId) -> (usize, usize) {
+    let mut queue = CommitQueue {
+        store,
+        heap: BinaryHeap::new(),
+        flags: HashMap::new(),
+        commits: HashMap::new(),
+        queued: HashMap::new(),
+        one_sided: 0,
+    };
+    queue.add(local, FROM_LOCAL);
+    queue.add(upstream, FROM_UPSTREAM);
+
+    let mut walked: HashMap<ObjectId, u8> = HashMap::new();
+    let mut walked_count = 0;
+    let mut oldest_one_sided = None;
+    while let Some((time, id, flag)) = queue.pop() {
+        if flag == FROM_BOTH && queue.one_sided == 0 {
+            // commits with the same time (or a wrong clock) may still be parents of commits that were
+            // only walked from one side, so keep going until they are older.
+            // only commits reachable from both are queued from now on, so the oldest can't get older
+            let oldest = *oldest_one_sided.get_or_insert_with(|| queue.oldest_one_sided());
+            if oldest.is_none_or(|oldest| time < oldest) {
+                break;
+            }
+        }
+        walked_count += 1;
+        if walked_count > MAX_COMMITS_WALKED {
+            break;
+        }
+        // already walked with the same flags
+        if walked.get(&id) == Some(&flag) {
+            continue;
+        }
+        walked.insert(id, flag);
+        let Some(Some(commit)) = queue.commits.get(&id) else {
+            continue;
+        };
+        for parent in commit.parents.clone() {
+            queue.add(parent, flag);
+        }
+    }
+
+    let ahead = queue
+        .flags
+        .values()
+        .filter(|flag| **flag == FROM_LOCAL)
+        .count();
+    let behind = queue
+        .flags
+        .values()
+        .filter(|flag| **flag == FROM_UPSTREAM)
+        .count();
+    (ahead, behind)
+}
+
+#[derive(Clone)]
+struct IndexEntry {
+    path: String,
+    /// Modification time of the file when it was added, as seconds and nanoseconds
+    modified: (u32, u32),
+    size: u32,
+    mode: u32,
+    id: ObjectId,
+    /// Merge stage, which is not 0 for conflicts
+    stage: u16,
+}
+
+/// Read the entries of the index. Only versions 2 and 3 are supported, since version 4 compresses paths.
+///
+/// Split and sparse indexes are also not supported, since they leave entries out
+fn read_index(path: &Path) -> Option<Vec<IndexEntry>> {
+    let data = fs::read(path).ok()?;
+    if data.get(0..4)? != b"DIRC" {
+        return None;
+    }
+    let version = read_u32(&data, 4)?;
+    if !(2..=3).contains(&version) {
+        return None;
+    }
+    let count = read_u32(&data, 8)? as usize;
+    // every entry takes at least 62 bytes, so a broken count can't cause a huge allocation
+    let mut entries = Vec::with_capacity(count.min(data.len() / 62));
+    let mut position = 12;
+    for _ in 0..count {
+        let start = position;
+        let flags = u16::from_be_bytes(data.get(start + 60..start + 62)?.try_into().ok()?);
+        let mut path_start = start + 62;
+        // extended flags
+        if version >= 3 && flags & 0x4000 != 0 {
+            path_start += 2;
+        }
+        let path_len = data.get(path_start..)?.iter().position(|byte| *byte == 0)?;
+        entries.push(IndexEntry {
+            path: String::from_utf8_lossy(&data[path_start..path_start + path_len]).to_string(),
+            modified: (read_u32(&data, start + 8)?, read_u32(&data, start + 12)?),
+            mode: read_u32(&data, start + 24)?,
+            size: read_u32(&data, start + 36)?,
+            id: ObjectId::try_from(data.get(start + 40..start + 60)?).ok()?,
+            stage: (flags >> 12) & 3,
+        });
+        // entries are padded with 1 to 8 null bytes, to a multiple of 8
+        let entry_len = path_start - start + path_len;
+        position = start + (entry_len + 8) / 8 * 8;
+    }
+    // extensions follow the entries, up to the checksum at the end
+    let end = data.len().checked_sub(20)?;
+    while position + 8 <= end {
+        let signature = data.get(position..position + 4)?;
+        if signature == b"link" || signature == b"sdir" {
+            return None;
+        }
+        position += 8 + read_u32(&data, position + 4)? as usize;
+    }
+    Some(entries)
+}
+
+/// Whether the index has changes compared to the tree of HEAD
+fn has_staged_changes(
+    store: &ObjectStore,
+    head_tree: Option<ObjectId>,
+    entries: &[IndexEntry],
+) -> bool {
+    let mut files = HashMap::new();
+    if let Some(tree) = head_tree
+        && store.flatten_tree(&tree, "", &mut files).is_none()
+    {
+        return false;
+    }
+    let mut matched = 0;
+    for entry in entries {
+        if entry.stage != 0 || files.get(&entry.path) != Some(&entry.id) {
+            return true;
+        }
+        matched += 1;
+    }
+    // files in HEAD that aren't in the index have been deleted
+    matched != files.len()
+}
+
+/// Whether any tracked file in the work tree differs from the index.
+///
+/// Files with the same size and modification time as in the index are assumed unchanged, like git does. Others are hashed.
+/// If git converts line endings, files are also hashed with CRLF turned into LF, since that is how they would be added.
+fn has_unstaged_changes(
+    work_tree: &Path,
+    entries: &[IndexEntry],
+    converts_line_endings: bool,
+) -> bool {
+    for entry in entries {
+        if entry.stage != 0 {
+            return true;
+        }
+        if entry.mode == MODE_GITLINK {
+            continue;
+        }
+        let path = work_tree.join(&entry.path);
+        let Ok(metadata) = fs::symlink_metadata(&path) else {
+            // deleted
+            return true;
+        };
+        if metadata.len() as u32 != entry.size {
+            return true;
+        }
+        let modified = metadata
+            .modified()
+            .ok()
+            .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+            .map(|modified| (modified.as_secs() as u32, modified.subsec_nanos()));
+        // some systems don't store nanoseconds in the index
+        let same_time = modified.is_some_and(|(seconds, nanos)| {
+            seconds == entry.modified.0 && (entry.modified.1 == 0 || nanos == entry.modified.1)
+        });
+        if same_time {
+            continue;
+        }
+        let contents = if entry.mode == MODE_SYMLINK {
+            fs::read_link(&path).map(|target| target.to_string_lossy().as_bytes().to_vec())
+        } else {
+            fs::read(&path)
+        };
+        let Ok(contents) = contents else {
+            return true;
+        };
+        if blob_id(&contents) == entry.id {
+            continue;
+        }
+        if converts_line_endings
+            && entry.mode != MODE_SYMLINK
+            && contents.windows(2).any(|pair| pair == b"\r\n")
+            && blob_id(&crlf_to_lf(&contents)) == entry.id
+        {
+            continue;
+        }
+        return true;
+    }
+    false
+}
+
+/// Id of a blob object with the contents
+fn blob_id(contents: &[u8]) -> ObjectId {
+    let mut hasher = sha1_smol::Sha1::new();
+    hasher.update(format!("blob {}\0", contents.len()).as_bytes());
+    hasher.update(contents);
+    hasher.digest().bytes()
+}
+
+fn crlf_to_lf(contents: &[u8]) -> Vec<u8> {
+    let mut converted = Vec::with_capacity(contents.len());
+    for (index, byte) in contents.iter().enumerate() {
+        if *byte == b'\r' && contents.get(index + 1) == Some(&b'\n') {
+            continue;
+        }
+        converted.push(*byte);
+    }
+    converted
+}
+
+/// Modification time and length of a file, used to tell when it has changed
+type FileStamp = (SystemTime, u64);
+
+fn stamp_of(path: &Path) -> Option<FileStamp> {
+    let metadata = fs::metadata(path).ok()?;
+    Some((metadata.modified().ok()?, metadata.len()))
+}
+
+#[derive(Default)]
+struct RepoCache {
+    /// Local and upstream commits, and how far ahead and behind the local one was
+    ahead_behind: Option<(ObjectId, ObjectId, (usize, usize))>,
+    /// The index when it was last read, and its entries. None if it couldn't be read
+    index: Option<(FileStamp, Option<Vec<IndexEntry>>)>,
+    /// HEAD commit and index when staged changes were last looked for, and whether there were any
+    staged: Option<(Option<ObjectId>, FileStamp, bool)>,
+    /// Index and time when unstaged changes were last looked for, and whether there were any
+    unstaged: Option<(FileStamp, Instant, bool)>,
+}
+
+/// Caches git status per repo, so the prompt stays fast
+#[derive(Default)]
+pub struct GitCache {
+    repos: HashMap<PathBuf, RepoCache>,
+}
+
+impl GitCache {
+    /// Status of the repo a directory is in. None if it isn't in a repo
+    pub fn status(&mut self, dir: &Path) -> Option<GitStatus> {
+        let repo = Repo::find(dir)?;
+        let cache = self.repos.entry(repo.git_dir.clone()).or_default();
+        // the object store is only opened if something has changed
+        let mut store: Option<ObjectStore> = None;
+
+        let (branch, head_id) = match repo.head()? {
+            Head::Branch(reference) => {
+                let name = reference
+                    .strip_prefix("refs/heads/")
+                    .unwrap_or(&reference)
+                    .to_string();
+                (name, repo.resolve_ref(&reference))
+            }
+            Head::Detached(id) => (to_hex(&id)[..7].to_string(), Some(id)),
+        };
+
+        let upstream_id = repo
+            .upstream_ref(&branch)
+            .and_then(|upstream| repo.resolve_ref(&upstream));
+        let (ahead, behind) = match (head_id, upstream_id) {
+            (Some(local), Some(upstream)) => match cache.ahead_behind {
+                Some((cached_local, cached_upstream, counts))
+                    if cached_local == local && cached_upstream == upstream =>
+                {
+                    counts
+                }
+                _ => {
+                    let store = store.get_or_insert_with(|| ObjectStore::open(&repo));
+                    let counts = ahead_behind(store, local, upstream);
+                    cache.ahead_behind = Some((local, upstream, counts));
+                    counts
+                }
+            },
+            _ => (0, 0),
+        };
+
+        let index_path = repo.git_dir.join("index");
+        let index_stamp = stamp_of(&index_path);
+        // when the index can't be read, changes aren't shown at all, instead of being wrong
+        let entries: Option<Vec<IndexEntry>> = match (&cache.index, index_stamp) {
+            (Some((stamp, entries)), Some(index_stamp)) if *stamp == index_stamp => entries.clone(),
+            (_, Some(index_stamp)) => {
+                let entries = read_index(&index_path);
+                cache.index = Some((index_stamp, entries.clone()));
+                entries
+            }
+            // no index yet, like in a new repo
+            (_, None) => Some(Vec::new()),
+        };
+
+        let staged = match (cache.staged, index_stamp, &entries) {
+            (_, _, None) => false,
+            (Some((cached_head, stamp, staged)), Some(index_stamp), _)
+                if cached_head == head_id && stamp == index_stamp =>
+            {
+                staged
+            }
+            (_, _, Some(entries)) => {
+                let store = store.get_or_insert_with(|| ObjectStore::open(&repo));
+                let head_tree = head_id
+                    .and_then(|id| store.read_commit(&id))
+                    .and_then(|commit| commit.tree);
+                let staged = has_staged_changes(store, head_tree, entries);
+                if let Some(index_stamp) = index_stamp {
+                    cache.staged = Some((head_id, index_stamp, staged));
+                }
+                staged
+            }
+        };
+
+        let dirty = match (cache.unstaged, index_stamp, &entries) {
+            (_, _, None) => false,
+            (Some((stamp, checked, dirty)), Some(index_stamp), _)
+                if stamp == index_stamp && checked.elapsed() < UNSTAGED_CACHE_TIME =>
+            {
+                dirty
+            }
+            (_, _, Some(entries)) => {
+                let dirty =
+                    has_unstaged_changes(&repo.work_tree, entries, repo.converts_line_endings());
+                if let Some(index_stamp) = index_stamp {
+                    cache.unstaged = Some((index_stamp, Instant::now(), dirty));
+                }
+                dirty
+            }
+        };
+
+        Some(GitStatus {
+            branch,
+            ahead,
+            behind,
+            staged,
+            dirty,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_hex_ids() {
+        let mut expected = [0; 20];
+        expected[0] = 0xab;
+        expected[19] = 0x01;
+        let hex = format!("ab{}01\n", "0".repeat(36));
+        assert_eq!(parse_hex(&hex), Some(expected));
+        assert_eq!(to_hex(&expected), hex.trim());
+
+        assert_eq!(parse_hex("abc"), None);
+        assert_eq!(parse_hex(&"g".repeat(40)), None);
+    }
+
+    #[test]
+    fn reads_varints() {
+        let data = [0x96, 0x01, 0x05, 0x80];
+        let mut position = 0;
+        assert_eq!(read_varint(&data, &mut position), Some(150));
+        assert_eq!(position, 2);
+        assert_eq!(read_varint(&data, &mut position), Some(5));
+        // cut off in the middle
+        assert_eq!(read_varint(&data, &mut position), None);
+    }
+
+    #[test]
+    fn applies_deltas() {
+        let base = b"hello world";
+        let delta = [
+            11, // base size
+            12, // result size
+            // copy 5 bytes from offset 6
+            0x80 | 0x01 | 0x10,
+            6,
+            5,
+            // insert 2 bytes
+            2,
+            b',',
+            b' ',
+            // copy 5 bytes from offset 0, which is left out
+            0x80 | 0x10,
+            5,
+        ];
+        assert_eq!(
+            apply_delta(base, &delta).as_deref(),
+            Some(&b"world, hello"[..])
+        );
+
+        // wrong result size
+        let mut wrong_size = delta;
+        wrong_size[1] = 13;
+        assert_eq!(apply_delta(base, &wrong_size), None);
+        // copying past the end of the base
+        assert_eq!(apply_delta(base, &[11, 5, 0x80 | 0x01 | 0x10, 8, 5]), None);
+        // instruction 0 is reserved
+        assert_eq!(apply_delta(base, &[11, 0, 0]), None);
+    }
+
+    fn index_entry(path: &str, size: u32) -> Vec<u8> {
+        let mut entry = vec![0; 62];
+        entry[8..12].copy_from_slice(&1_700_000_000u32.to_be_bytes());
+        entry[24..28].copy_from_slice(&0o100644u32.to_be_bytes());
+        entry[36..40].copy_from_slice(&size.to_be_bytes());
+        entry[40..60].fill(0x11);
+        entry[60..62].copy_from_slice(&(path.len() as u16).to_be_bytes());
+        entry.extend_from_slice(path.as_bytes());
+        // padded with 1 to 8 null bytes, to a multiple of 8
+        let padded_len = (entry.len() + 8) / 8 * 8;
+        entry.resize(padded_len, 0);
+        entry
+    }
+
+    fn index_file(version: u32, entries: &[Vec<u8>], extensions: &[u8]) -> Vec<u8> {
+        let mut data = b"DIRC".to_vec();
+        data.extend_from_slice(&version.to_be_bytes());
+        data.extend_from_slice(&(entries.len() as u32).to_be_bytes());
+        for entry in entries {
+            data.extend_from_slice(entry);
+        }
+        data.extend_from_slice(extensions);
+        // checksum
+        data.extend_from_slice(&[0; 20]);
+        data
+    }
+
+    fn read_index_data(name: &str, data: &[u8]) -> Option<Vec<IndexEntry>> {
+        let path =
+            std::env::temp_dir().join(format!("shoe-index-test-{}-{}", std::process::id(), name));
+        fs::write(&path, data).unwrap();
+        let entries = read_index(&path);
+        fs::remove_file(&path).unwrap();
+        entries
+    }
+
+    #[test]
+    fn reads_index_entries_with_padding() {
+        // "a" needs 1 byte of padding and "ab" a whole 8
+        let entries = [
+            index_entry("a", 3),
+            index_entry("ab", 4),
+            index_entry("dir/file.txt", 5),
+        ];
+        assert_eq!(entries[0].len(), 64);
+        assert_eq!(entries[1].len(), 72);
+
+        let tree_extension = [b"TREE".as_slice(), &2u32.to_be_bytes(), &[0, 0]].concat();
+        let data = index_file(2, &entries, &tree_extension);
+        let read = read_index_data("padding", &data).unwrap();
+        let paths: Vec<&str> = read.iter().map(|entry| entry.path.as_str()).collect();
+        assert_eq!(paths, ["a", "ab", "dir/file.txt"]);
+        assert_eq!(read[2].size, 5);
+        assert_eq!(read[2].mode, 0o100644);
+        assert_eq!(read[2].modified, (1_700_000_000, 0));
+        assert_eq!(read[2].id, [0x11; 20]);
+    }
+
+    #[test]
+    fn rejects_unsupported_indexes() {
+        let entries = [index_entry("a", 3)];
+        assert!(read_index_data("v4", &index_file(4, &entries, &[])).is_none());
+
+        let link_extension = [b"link".as_slice(), &20u32.to_be_bytes(), &[0; 20]].concat();
+        let split = index_file(2, &entries, &link_extension);
+        assert!(read_index_data("split", &split).is_none());
+
+        let sparse_extension = [b"sdir".as_slice(), &0u32.to_be_bytes()].concat();
+        let sparse = index_file(2, &entries, &sparse_extension);
+        assert!(read_index_data("sparse", &sparse).is_none());
+    }
+
+    #[test]
+    fn rejects_huge_counts() {
+        let mut data = index_file(2, &[index_entry("a", 3)], &[]);
+        data[8..12].copy_from_slice(&u32::MAX.to_be_bytes());
+        assert!(read_index_data("count", &data).is_none());
+        let delta = [
+            0x80, 0x80, 0x80, 0x80, 0x0f, 0x80, 0x80, 0x80, 0x80, 0x0f, 0x01, b'a',
+        ];
+        assert_eq!(apply_delta(b"", &delta), None);
+    }
+
+    #[test]
+    fn hashes_files_with_converted_line_endings() {
+        let work_tree = std::env::temp_dir().join(format!("shoe-crlf-test-{}", std::process::id()));
+        fs::create_dir_all(&work_tree).unwrap();
+        fs::write(work_tree.join("file"), "a\r\nb\r\n").unwrap();
+        let entries = [IndexEntry {
+            path: String::from("file"),
+            modified: (0, 0),
+            size: 6,
+            mode: 0o100644,
+            id: blob_id(b"a\nb\n"),
+            stage: 0,
+        }];
+        assert!(has_unstaged_changes(&work_tree, &entries, false));
+        assert!(!has_unstaged_changes(&work_tree, &entries, true));
+        fs::remove_dir_all(work_tree).unwrap();
+    }
+}
@@ -0,0 +1,448 @@
+//! Persistent command history, stored at ~/.shoehistory
+//!
+//! Each entry is stored on its own line, as tab seperated fields: timestamp, duration, exit code, cwd and the command itself.
+//! Tabs, newlines and backslashes in fields are escaped, so multi-line commands don't break the file.
+//!
+//! The file is only ever appended to, except when migrating an older plain text history file, or deleting or trimming entries.
+//! All reads and writes are done while holding a lock on the file, so several sessions can share it safely.
+//! Rewriting the file increases the generation number in its header, so other sessions know to read it again from the start.
+
+use std::{
+    fs::{self, OpenOptions},
+    io::{self, Read, Seek, SeekFrom, Write},
+};
+
+use crate::utils::wildcard_match;
+
+/// Start of the first line of a history file in the current format, followed by the generation number.
+/// Files without it are treated as old plain text history.
+const HISTORY_HEADER: &str = "#shoe history v2";
+
+#[derive(Clone, Debug)]
+pub struct HistoryEntry {
+    pub command: String,
+    /// Unix timestamp (in seconds) of when the command was run. Is 0 for entries migrated from the old format
+    pub timestamp: u64,
+    /// The working directory the command was run in
+    pub cwd: String,
+    /// How long the command took to run, in milliseconds
+    pub duration: u64,
+    pub exit_code: i32,
+}
+
+impl HistoryEntry {
+    fn to_line(&self) -> String {
+        format!(
+            "{}\t{}\t{}\t{}\t{}",
+            self.timestamp,
+            self.duration,
+            self.exit_code,
+            escape(&self.cwd),
+            escape(&self.command)
+        )
+    }
+    fn from_line(line: &str) -> Option<Self> {
+        let mut fields = line.splitn(5, '\t');
+        let timestamp = fields.next()?.parse().ok()?;
+        let duration = fields.next()?.parse().ok()?;
+        let exit_code = fields.next()?.parse().ok()?;
+        let cwd = unescape(fields.next()?);
+        let command = unescape(fields.next()?);
+        Some(HistoryEntry {
+            command,
+            timestamp,
+            cwd,
+            duration,
+            exit_code,
+        })
+    }
+}
+
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for char in text.chars() {
+        match char {
+            '\\' => escaped += "\\\\",
+            '\t' => escaped += "\\t",
+            '\n' => escaped += "\\n",
+            '\r' => escaped += "\\r",
+            _ => escaped.push(char),
+        }
+    }
+    escaped
+}
+
+fn unescape(text: &str) -> String {
+    let mut unescaped = String::with_capacity(text.len());
+    let mut chars = text.chars();
+    while let Some(char) = chars.next() {
+        if char != '\\' {
+            unescaped.push(char);
+            continue;
+        }
+        match chars.next() {
+            Some('t') => unescaped.push('\t'),
+            Some('n') => unescaped.push('\n'),
+            Some('r') => unescaped.push('\r'),
+            Some(other) => unescaped.push(other),
+            None => unescaped.push('\\'),
+        }
+    }
+    unescaped
+}
+
+/// Handle to the history file.
+///
+/// Multiple shoe sessions may use the same history file at once, so the file is locked while reading and writing,
+/// and only rewritten from its own contents. Keeps track of how much of the file has been read, so entries appended by other sessions can be picked up.
+pub struct HistoryFile {
+    path: String,
+    /// Length of the file when it was last read
+    read_len: u64,
+    /// Generation of the file when it was last read. Changes whenever the file is rewritten
+    generation: u64,
+}
+
+/// Entries read from the history file since it was last read
+pub enum NewEntries {
+    /// Entries that other sessions appended
+    Appended(Vec<HistoryEntry>),
+    /// The file has been rewritten, so these are all its entries
+    Rewritten(Vec<HistoryEntry>),
+}
+
+impl HistoryFile {
+    /// Open a history file and read all its entries.
+    ///
+    /// If the file is in the old plain text format (one command per line), it is rewritten in the new format.
+    pub fn open(path: String) -> io::Result<(Self, Vec<HistoryEntry>)> {
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(&path)?;
+        file.lock()?;
+
+        let mut text = String::new();
+        file.read_to_string(&mut text)?;
+
+        let (generation, entries) = match parse_header(&text) {
+            Some((generation, body)) => (generation, parse_entries(body)),
+            None => {
+                // old format, every non empty line is a command
+                let entries: Vec<HistoryEntry> = text
+                    .lines()
+                    .filter(|line| !line.trim().is_empty())
+                    .map(|line| HistoryEntry {
+                        command: line.to_string(),
+                        timestamp: 0,
+                        cwd: String::new(),
+                        duration: 0,
+                        exit_code: 0,
+                    })
+                    .collect();
+                write_all_entries(&mut file, 1, &entries)?;
+                (1, entries)
+            }
+        };
+        let read_len = file.metadata()?.len();
+        file.unlock()?;
+
+        let history_file = HistoryFile {
+            path,
+            read_len,
+            generation,
+        };
+        Ok((history_file, entries))
+    }
+
+    /// Read entries that have been appended to the file by other sessions since it was last read
+    pub fn read_new(&mut self) -> io::Result<NewEntries> {
+        // the file may have been deleted since, so create it again rather than failing
+        let mut file = OpenOptions::new()
+            .read(true)
+            .append(true)
+            .create(true)
+            .open(&self.path)?;
+        file.lock_shared()?;
+        let entries = self.read_from_last_position(&mut file);
+        file.unlock()?;
+        entries
+    }
+
+    /// Delete the entry at `index` from the file, as long as it is still `entry`.
+    ///
+    /// Other sessions may have changed the file (or, without shared history, written entries this session doesn't have),
+    /// so otherwise the last entry with the same timestamp and command is deleted.
+    /// Returns all entries left in the file, including ones other sessions have written.
+    pub fn delete(&mut self, index: usize, entry: &HistoryEntry) -> io::Result<Vec<HistoryEntry>> {
+        let is_entry = |other: &HistoryEntry| {
+            other.timestamp == entry.timestamp && other.command == entry.command
+        };
+        self.change_entries(|entries| {
+            // entries migrated from the old format all have the same timestamp, so the index is needed to tell repeated commands apart
+            let position = if entries.get(index).is_some_and(is_entry) {
+                Some(index)
+            } else {
+                entries.iter().rposition(is_entry)
+            };
+            if let Some(position) = position {
+                entries.remove(position);
+            }
+        })
+    }
+
+    /// Delete every entry in the file
+    pub fn clear(&mut self) -> io::Result<()> {
+        self.change_entries(Vec::clear)?;
+        Ok(())
+    }
+
+    /// Only keep the most recent `size` entries in the file. Returns the entries that are kept
+    pub fn trim(&mut self, size: usize) -> io::Result<Vec<HistoryEntry>> {
+        self.change_entries(|entries| {
+            if entries.len() > size {
+                entries.drain(..entries.len() - size);
+            }
+        })
+    }
+
+    /// Change the entries in the file and write them back.
+    ///
+    /// The whole file is read again while it is locked, so entries written by other sessions are never lost. Returns the new entries of the file
+    fn change_entries(
+        &mut self,
+        change: impl FnOnce(&mut Vec<HistoryEntry>),
+    ) -> io::Result<Vec<HistoryEntry>> {
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(&self.path)?;
+        file.lock()?;
+        let result = self.change_locked_entries(&mut file, change);
+        file.unlock()?;
+        result
+    }
+
+    fn change_locked_entries(
+        &mut self,
+        file: &mut fs::File,
+        change: impl FnOnce(&mut Vec<HistoryEntry>),
+    ) -> io::Result<Vec<HistoryEntry>> {
+        let mut text = String::new();
+        file.read_to_string(&mut text)?;
+        let (generation, body) = parse_header(&text).unwrap_or((0, ""));
+        let mut entries = parse_entries(body);
+        change(&mut entries);
+
+        self.generation = generation + 1;
+        write_all_entries(file, self.generation, &entries)?;
+        self.read_len = file.metadata()?.len();
+        Ok(entries)
+    }
+
+    /// Append a single entry to the end of the file.
+    ///
+    /// Returns any entries that other sessions appended since the file was last read.
+    pub fn append(&mut self, entry: &HistoryEntry) -> io::Result<NewEntries> {
+        let mut file = OpenOptions::new()
+            .read(true)
+            .append(true)
+            .create(true)
+            .open(&self.path)?;
+        file.lock()?;
+        let result = self
+            .read_from_last_position(&mut file)
+            .and_then(|new_entries| {
+                // write the whole line at once, so no other session can read half an entry
+                file.write_all(format!("{}\n", entry.to_line()).as_bytes())?;
+                self.read_len = file.metadata()?.len();
+                Ok(new_entries)
+            });
+        file.unlock()?;
+        result
+    }
+
+    fn read_from_last_position(&mut self, file: &mut fs::File) -> io::Result<NewEntries> {
+        let len = file.metadata()?.len();
+        file.seek(SeekFrom::Start(0))?;
+        let mut header = Vec::new();
+        Read::by_ref(file).take(64).read_to_end(&mut header)?;
+        let generation =
+            parse_header(&String::from_utf8_lossy(&header)).map(|(generation, _)| generation);
+
+        // if the file has been rewritten (or has shrunk, if something else rewrote it), what was read before
+        // may have changed, so read it all again
+        if generation != Some(self.generation) || len < self.read_len {
+            file.seek(SeekFrom::Start(0))?;
+            let mut text = String::new();
+            file.read_to_string(&mut text)?;
+            let (generation, body) = parse_header(&text).unwrap_or((0, ""));
+            self.generation = generation;
+            self.read_len = len;
+            return Ok(NewEntries::Rewritten(parse_entries(body)));
+        }
+        if len == self.read_len {
+            return Ok(NewEntries::Appended(Vec::new()));
+        }
+        file.seek(SeekFrom::Start(self.read_len))?;
+        let mut text = String::new();
+        file.read_to_string(&mut text)?;
+        self.read_len = len;
+        Ok(NewEntries::Appended(parse_entries(&text)))
+    }
+}
+
+/// Split a history file into its generation and the lines of entries after the header.
+/// None if the file is in the old plain text format
+fn parse_header(text: &str) -> Option<(u64, &str)> {
+    let rest = text.strip_prefix(HISTORY_HEADER)?;
+    let (first_line, body) = rest.split_once('\n').unwrap_or((rest, ""));
+    // files written before generations were added have none
+    let generation = first_line.trim().parse().unwrap_or(0);
+    Some((generation, body))
+}
+
+fn parse_entries(text: &str) -> Vec<HistoryEntry> {
+    text.lines().filter_map(HistoryEntry::from_line).collect()
+}
+
+/// Rewrite a locked history file in the current format
+fn write_all_entries(
+    file: &mut fs::File,
+    generation: u64,
+    entries: &[HistoryEntry],
+) -> io::Result<()> {
+    let mut text = format!("{} {}\n", HISTORY_HEADER, generation);
+    for entry in entries {
+        text += &entry.to_line();
+        text.push('\n');
+    }
+    file.set_len(0)?;
+    file.seek(SeekFrom::Start(0))?;
+    file.write_all(text.as_bytes())
+}
+
+/// Pattern for commands that should never be stored in history
+pub enum IgnorePattern {
+    /// Wildcard pattern, matched against the whole command
+    Glob(String),
+    Regex(regex::Regex),
+}
+
+impl IgnorePattern {
+    /// Parse a pattern. Patterns starting with `re:` are regular expressions, all others are wildcard patterns
+    pub fn parse(pattern: &str) -> io::Result<Self> {
+        match pattern.strip_prefix("re:") {
+            Some(regex) => {
+                let regex = regex::Regex::new(regex).map_err(|error| {
+                    io::Error::other(format!(
+                        "invalid history ignore pattern '{}': {}",
+                        pattern, error
+                    ))
+                })?;
+                Ok(IgnorePattern::Regex(regex))
+            }
+            None => Ok(IgnorePattern::Glob(pattern.to_string())),
+        }
+    }
+
+    pub fn matches(&self, command: &str) -> bool {
+        match self {
+            IgnorePattern::Glob(pattern) => wildcard_match(pattern, command),
+            IgnorePattern::Regex(regex) => regex.is_match(command),
+        }
+    }
+}
+
+/// Read history ignore patterns from a file, one per line.
+///
+/// Lines starting with `re:` are regular expressions, all others are wildcard patterns, like `*TOKEN=*`.
+/// Empty lines and lines starting with `#` are skipped. If the file doesn't exist, there are no patterns.
+pub fn load_ignore_patterns(path: &str) -> io::Result<Vec<IgnorePattern>> {
+    let text = match fs::read_to_string(path) {
+        Ok(text) => text,
+        Err(error) if error.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(error) => return Err(error),
+    };
+    let mut patterns = Vec::new();
+    for line in text.lines() {
+        if line.trim().is_empty() || line.starts_with('#') {
+            continue;
+        }
+        patterns.push(IgnorePattern::parse(line)?);
+    }
+    Ok(patterns)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(command: &str, timestamp: u64) -> HistoryEntry {
+        HistoryEntry {
+            command: command.to_string(),
+            timestamp,
+            cwd: String::from("/"),
+            duration: 0,
+            exit_code: 0,
+        }
+    }
+
+    fn commands(entries: &[HistoryEntry]) -> Vec<&str> {
+        entries.iter().map(|entry| entry.command.as_str()).collect()
+    }
+
+    fn temp_history_path(name: &str) -> String {
+        let path =
+            std::env::temp_dir().join(format!("shoe-history-test-{}-{}", std::process::id(), name));
+        let _ = fs::remove_file(&path);
+        path.to_string_lossy().to_string()
+    }
+
+    #[test]
+    fn delete_keeps_entries_of_other_sessions() {
+        let path = temp_history_path("delete");
+        let (mut first, _) = HistoryFile::open(path.clone()).unwrap();
+        let (mut second, _) = HistoryFile::open(path.clone()).unwrap();
+
+        first.append(&entry("ls", 1)).unwrap();
+        second.append(&entry("echo hi", 2)).unwrap();
+        first.append(&entry("pwd", 3)).unwrap();
+
+        // the first session never kept "echo hi" in memory, but deleting shouldn't remove it
+        let entries = first.delete(0, &entry("ls", 1)).unwrap();
+        assert_eq!(commands(&entries), ["echo hi", "pwd"]);
+
+        // the second session sees that the file was rewritten, and reads all of it again
+        second.append(&entry("cd", 4)).unwrap();
+        let (_, entries) = HistoryFile::open(path.clone()).unwrap();
+        assert_eq!(commands(&entries), ["echo hi", "pwd", "cd"]);
+        match first.read_new().unwrap() {
+            NewEntries::Appended(entries) => assert_eq!(commands(&entries), ["cd"]),
+            NewEntries::Rewritten(_) => panic!("file wasn't rewritten"),
+        }
+
+        second.clear().unwrap();
+        match first.read_new().unwrap() {
+            NewEntries::Rewritten(entries) => assert!(entries.is_empty()),
+            NewEntries::Appended(_) => panic!("file was rewritten"),
+        }
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn delete_repeated_migrated_command() {
+        let path = temp_history_path("migrated");
+        fs::write(&path, "ls\npwd\nls\n").unwrap();
+        let (mut history_file, entries) = HistoryFile::open(path.clone()).unwrap();
+        assert!(entries.iter().all(|entry| entry.timestamp == 0));
+
+        let entries = history_file.delete(0, &entries[0]).unwrap();
+        assert_eq!(commands(&entries), ["pwd", "ls"]);
+        fs::remove_file(path).unwrap();
+    }
+}
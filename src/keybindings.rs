@@ -0,0 +1,356 @@
+//! Key bindings, from the `[keybindings]` table of the config file
+//!
+//! Keys are written like `ctrl+w`, `alt+backspace` or `shift+left`. A key can be bound to an action, like `"ctrl+k" = "kill-line"`,
+//! to another key, which it then acts like, like `"ctrl+h" = "backspace"`, or to `none` to unbind it.
+//!
+//! In vi mode, the bindings are used in insert mode. Normal mode has the usual vi keys.
+
+use std::{collections::HashMap, io};
+
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+
+/// A key, with the modifiers held down
+pub type Key = (KeyCode, KeyModifiers);
+
+/// Things keys can do in the line editor
+#[derive(Clone, Copy, PartialEq)]
+pub enum Action {
+    /// Run the input
+    AcceptLine,
+    /// Throw away the input and start over on a new line
+    Cancel,
+    ClearLine,
+    Complete,
+    /// Move right. At the end of the input, fills in the suggestion
+    ForwardChar,
+    BackwardChar,
+    /// Move to the end of the next word. At the end of the input, fills in the next word of the suggestion
+    ForwardWord,
+    BackwardWord,
+    BeginningOfLine,
+    EndOfLine,
+    DeleteChar,
+    BackwardDeleteChar,
+    /// Delete the text from the cursor to the end of the word, so it can be yanked back
+    KillWord,
+    BackwardKillWord,
+    /// Delete the text from the cursor to the end of the input
+    KillLine,
+    BackwardKillLine,
+    KillWholeLine,
+    /// Insert the text that was last killed
+    Yank,
+    /// Swap the character before the cursor with the one under it
+    TransposeChars,
+    /// Fill in the whole suggestion
+    AcceptSuggestion,
+    /// Fill in the next word of the suggestion, or move right if not at the end of the input
+    AcceptSuggestionWord,
+    HistoryPrevious,
+    HistoryNext,
+    /// Move, selecting the text moved over
+    SelectForwardChar,
+    SelectBackwardChar,
+    SelectForwardWord,
+    SelectBackwardWord,
+    SelectBeginningOfLine,
+    SelectEndOfLine,
+    /// Copy the selected text to the clipboard
+    Copy,
+    /// Copy the selected text to the clipboard, and delete it
+    Cut,
+    /// Insert the text on the clipboard, replacing the selected text
+    Paste,
+    /// Copy if text is selected, otherwise cancel
+    CopyOrCancel,
+    ClearScreen,
+    /// Edit the input in $VISUAL or $EDITOR
+    OpenInEditor,
+    /// Switch to vi normal mode
+    ViNormalMode,
+    /// Switch to vi insert mode
+    ViInsertMode,
+    /// Move right, then switch to vi insert mode
+    ViAppend,
+    /// Insert the text that was last killed after the cursor
+    ViPaste,
+}
+
+impl Action {
+    const NAMES: &[(&str, Action)] = &[
+        ("accept-line", Action::AcceptLine),
+        ("cancel", Action::Cancel),
+        ("clear-line", Action::ClearLine),
+        ("complete", Action::Complete),
+        ("forward-char", Action::ForwardChar),
+        ("backward-char", Action::BackwardChar),
+        ("forward-word", Action::ForwardWord),
+        ("backward-word", Action::BackwardWord),
+        ("beginning-of-line", Action::BeginningOfLine),
+        ("end-of-line", Action::EndOfLine),
+        ("delete-char", Action::DeleteChar),
+        ("backward-delete-char", Action::BackwardDeleteChar),
+        ("kill-word", Action::KillWord),
+        ("backward-kill-word", Action::BackwardKillWord),
+        ("kill-line", Action::KillLine),
+        ("backward-kill-line", Action::BackwardKillLine),
+        ("kill-whole-line", Action::KillWholeLine),
+        ("yank", Action::Yank),
+        ("transpose-chars", Action::TransposeChars),
+        ("accept-suggestion", Action::AcceptSuggestion),
+        ("accept-suggestion-word", Action::AcceptSuggestionWord),
+        ("history-previous", Action::HistoryPrevious),
+        ("history-next", Action::HistoryNext),
+        ("select-forward-char", Action::SelectForwardChar),
+        ("select-backward-char", Action::SelectBackwardChar),
+        ("select-forward-word", Action::SelectForwardWord),
+        ("select-backward-word", Action::SelectBackwardWord),
+        ("select-beginning-of-line", Action::SelectBeginningOfLine),
+        ("select-end-of-line", Action::SelectEndOfLine),
+        ("copy", Action::Copy),
+        ("cut", Action::Cut),
+        ("paste", Action::Paste),
+        ("copy-or-cancel", Action::CopyOrCancel),
+        ("clear-screen", Action::ClearScreen),
+        ("open-in-editor", Action::OpenInEditor),
+        ("vi-normal-mode", Action::ViNormalMode),
+    ];
+
+    fn from_name(name: &str) -> Option<Self> {
+        Self::NAMES
+            .iter()
+            .find(|(key, _)| *key == name)
+            .map(|(_, action)| *action)
+    }
+}
+
+/// Bindings that are there unless the config file changes them
+const DEFAULT_BINDINGS: &[(&str, &str)] = &[
+    ("enter", "accept-line"),
+    ("ctrl+c", "copy-or-cancel"),
+    ("esc", "clear-line"),
+    ("tab", "complete"),
+    ("right", "forward-char"),
+    ("ctrl+f", "forward-char"),
+    ("left", "backward-char"),
+    ("ctrl+b", "backward-char"),
+    ("ctrl+right", "forward-word"),
+    ("alt+f", "forward-word"),
+    ("ctrl+left", "backward-word"),
+    ("alt+b", "backward-word"),
+    ("home", "beginning-of-line"),
+    ("ctrl+a", "beginning-of-line"),
+    ("end", "end-of-line"),
+    ("ctrl+e", "end-of-line"),
+    ("delete", "delete-char"),
+    ("backspace", "backward-delete-char"),
+    ("alt+d", "kill-word"),
+    ("ctrl+backspace", "backward-kill-word"),
+    ("alt+backspace", "backward-kill-word"),
+    ("ctrl+w", "backward-kill-word"),
+    ("ctrl+k", "kill-line"),
+    ("ctrl+u", "backward-kill-line"),
+    ("ctrl+y", "yank"),
+    ("ctrl+t", "transpose-chars"),
+    ("alt+right", "accept-suggestion"),
+    ("up", "history-previous"),
+    ("ctrl+p", "history-previous"),
+    ("down", "history-next"),
+    ("ctrl+n", "history-next"),
+    ("shift+right", "select-forward-char"),
+    ("shift+left", "select-backward-char"),
+    ("ctrl+shift+right", "select-forward-word"),
+    ("ctrl+shift+left", "select-backward-word"),
+    ("shift+home", "select-beginning-of-line"),
+    ("shift+end", "select-end-of-line"),
+    ("ctrl+x", "cut"),
+    ("ctrl+v", "paste"),
+    ("ctrl+l", "clear-screen"),
+    ("alt+e", "open-in-editor"),
+];
+
+/// Parse a key like `ctrl+shift+left` or `alt+x`
+pub fn parse_key(text: &str) -> io::Result<Key> {
+    let invalid = || io::Error::other(format!("Invalid key '{}'", text));
+    let lowercase = text.to_lowercase();
+    // the last part is the key, which can itself be +
+    let (modifier_names, name) = match lowercase.strip_suffix("++") {
+        Some(modifier_names) => (modifier_names, "+"),
+        None => match lowercase.rsplit_once('+') {
+            Some((modifier_names, name)) => (modifier_names, name),
+            None => ("", lowercase.as_str()),
+        },
+    };
+
+    let mut modifiers = KeyModifiers::NONE;
+    for modifier in modifier_names.split('+').filter(|name| !name.is_empty()) {
+        modifiers |= match modifier {
+            "ctrl" | "control" => KeyModifiers::CONTROL,
+            "alt" => KeyModifiers::ALT,
+            "shift" => KeyModifiers::SHIFT,
+            _ => return Err(invalid()),
+        };
+    }
+
+    let code = match name {
+        "backspace" => KeyCode::Backspace,
+        "enter" | "return" => KeyCode::Enter,
+        "left" => KeyCode::Left,
+        "right" => KeyCode::Right,
+        "up" => KeyCode::Up,
+        "down" => KeyCode::Down,
+        "home" => KeyCode::Home,
+        "end" => KeyCode::End,
+        "delete" | "del" => KeyCode::Delete,
+        "tab" => KeyCode::Tab,
+        "esc" | "escape" => KeyCode::Esc,
+        "space" => KeyCode::Char(' '),
+        _ => {
+            let mut chars = name.chars();
+            match (chars.next(), chars.next()) {
+                (Some(char), None) => KeyCode::Char(char),
+                (Some('f'), Some(_)) => KeyCode::F(name[1..].parse().map_err(|_| invalid())?),
+                _ => return Err(invalid()),
+            }
+        }
+    };
+    Ok((code, modifiers))
+}
+
+pub struct KeyMap {
+    /// Keys that act like other keys
+    remaps: HashMap<Key, Key>,
+    actions: HashMap<Key, Action>,
+}
+
+impl KeyMap {
+    /// The default bindings, with the bindings from the config file added on top.
+    ///
+    /// In vi mode, escape switches to normal mode instead of clearing the input
+    pub fn new(config_bindings: &[(String, String)], vi_mode: bool) -> io::Result<Self> {
+        let mut keymap = KeyMap {
+            remaps: HashMap::new(),
+            actions: HashMap::new(),
+        };
+        let mut defaults: Vec<(String, String)> = DEFAULT_BINDINGS
+            .iter()
+            .map(|(key, target)| (key.to_string(), target.to_string()))
+            .collect();
+        if vi_mode {
+            defaults.push(("esc".to_string(), "vi-normal-mode".to_string()));
+        }
+        for (key, target) in defaults.iter().chain(config_bindings) {
+            let key = parse_key(key)?;
+            keymap.remaps.remove(&key);
+            keymap.actions.remove(&key);
+            if target == "none" {
+                continue;
+            }
+            match Action::from_name(target) {
+                Some(action) => {
+                    keymap.actions.insert(key, action);
+                }
+                None => {
+                    let target = parse_key(target).map_err(|_| {
+                        io::Error::other(format!("'{}' is not an action or a key", target))
+                    })?;
+                    keymap.remaps.insert(key, target);
+                }
+            }
+        }
+        Ok(keymap)
+    }
+
+    /// Make a key event act like the key it is bound to, if any
+    pub fn apply(&self, event: &mut KeyEvent) {
+        if let Some((code, modifiers)) = self.remaps.get(&(event.code, event.modifiers)) {
+            event.code = *code;
+            event.modifiers = *modifiers;
+        }
+    }
+
+    /// The action a key is bound to, if any. Keys held with shift do the same as without, unless bound to something else
+    pub fn action(&self, event: &KeyEvent) -> Option<Action> {
+        self.actions
+            .get(&(event.code, event.modifiers))
+            .or_else(|| {
+                let modifiers = event.modifiers - KeyModifiers::SHIFT;
+                self.actions.get(&(event.code, modifiers))
+            })
+            .copied()
+    }
+}
+
+impl Default for KeyMap {
+    fn default() -> Self {
+        KeyMap::new(&[], false).unwrap()
+    }
+}
+
+/// What a key does in vi normal mode
+pub enum ViCommand {
+    /// Do these actions
+    Actions(&'static [Action]),
+    /// Wait for a motion to apply an operator (like `d` or `c`) to
+    Operator(char),
+    /// The key isn't a vi key, so it does what it's bound to
+    Default,
+}
+
+/// Get what a key does in vi normal mode. `pending` is an operator typed before it, if any.
+///
+/// Keys that aren't vi keys (like ctrl+l) use their usual bindings
+pub fn vi_normal_command(event: &KeyEvent, pending: Option<char>) -> ViCommand {
+    use Action::*;
+    let KeyCode::Char(char) = event.code else {
+        return ViCommand::Default;
+    };
+    if event
+        .modifiers
+        .intersects(KeyModifiers::CONTROL | KeyModifiers::ALT)
+    {
+        return ViCommand::Default;
+    }
+    // operators, like `dw` or `cc`
+    if let Some(operator) = pending {
+        let kill: &'static [Action] = match (operator, char) {
+            ('d', 'd') => &[KillWholeLine],
+            ('d', 'w' | 'e') => &[KillWord],
+            ('d', 'b') => &[BackwardKillWord],
+            ('d', '$') => &[KillLine],
+            ('d', '0' | '^') => &[BackwardKillLine],
+            ('c', 'c') => &[KillWholeLine, ViInsertMode],
+            ('c', 'w' | 'e') => &[KillWord, ViInsertMode],
+            ('c', 'b') => &[BackwardKillWord, ViInsertMode],
+            ('c', '$') => &[KillLine, ViInsertMode],
+            ('c', '0' | '^') => &[BackwardKillLine, ViInsertMode],
+            _ => &[],
+        };
+        return ViCommand::Actions(kill);
+    }
+    let actions: &'static [Action] = match char {
+        'h' => &[BackwardChar],
+        'l' | ' ' => &[ForwardChar],
+        'w' | 'e' => &[ForwardWord],
+        'b' => &[BackwardWord],
+        '0' | '^' => &[BeginningOfLine],
+        '$' => &[EndOfLine],
+        'k' => &[HistoryPrevious],
+        'j' => &[HistoryNext],
+        'i' => &[ViInsertMode],
+        'a' => &[ViAppend],
+        'I' => &[BeginningOfLine, ViInsertMode],
+        'A' => &[EndOfLine, ViInsertMode],
+        'x' => &[DeleteChar],
+        'X' => &[BackwardDeleteChar],
+        'D' => &[KillLine],
+        'C' => &[KillLine, ViInsertMode],
+        'S' => &[KillWholeLine, ViInsertMode],
+        'p' => &[ViPaste],
+        'P' => &[Yank],
+        'v' => &[OpenInEditor],
+        'd' | 'c' => return ViCommand::Operator(char),
+        _ => &[],
+    };
+    ViCommand::Actions(actions)
+}
@@ -1,28 +1,50 @@
 use binaryfinder::get_script_runtime;
 use commands::{COMMANDS, CommandContext};
+use config::Config;
+use copypasta::ClipboardProvider;
 use crossterm::{
-    cursor::{MoveDown, MoveRight, MoveToColumn, MoveUp},
+    cursor::{MoveDown, MoveRight, MoveTo, MoveToColumn, MoveUp},
     event::{
         self, Event, KeyCode, KeyEventKind, KeyModifiers, KeyboardEnhancementFlags,
         PushKeyboardEnhancementFlags,
     },
     queue,
-    style::{Color, SetAttribute, SetForegroundColor},
+    style::{Attribute, Color, SetAttribute, SetForegroundColor},
     terminal::{self, Clear, ClearType, disable_raw_mode, enable_raw_mode},
 };
+use frecency::DirectoryDatabase;
+use git::{GitCache, GitStatus};
+use history::{HistoryEntry, HistoryFile, IgnorePattern, NewEntries};
+use keybindings::{Action, KeyMap, ViCommand};
+use prompt::{Placeholder, PromptKind, PromptTemplate, RenderedPrompt};
 use relative_path::RelativePathBuf;
 use std::{
-    collections::{HashMap, VecDeque},
+    collections::{HashMap, HashSet, VecDeque},
     env, fmt,
-    io::{self, Read, Write, stdout},
+    hash::{BuildHasher, Hasher, RandomState},
+    io::{self, Read, Write, stderr, stdout},
     path::{Path, PathBuf},
     process::{self, Stdio},
+    time::{Instant, SystemTime, UNIX_EPOCH},
 };
-#[allow(unused)]
-use utils::{DEBUG_THEME, THEMES, Theme};
+use theme::{DEBUG_THEME, THEMES, Theme};
+use utils::format_duration;
 mod binaryfinder;
 mod commands;
+mod config;
+mod filters;
+mod find;
+mod frecency;
+mod git;
+mod history;
+mod keybindings;
+mod prompt;
+mod records;
+mod theme;
+mod transfer;
+mod trash;
 mod utils;
+mod viewer;
 
 fn count_occurence_in_string(text: &str, c: char) -> usize {
     let mut count = 0;
@@ -65,14 +87,13 @@ fn try_eval(text: &str) -> Result<f64, meval::Error> {
 
 /// Function parse line to arguments, with support for quote enclosures
 ///
-/// Include seperators will ensure no character of text is lost
-fn parse_text_to_tokens(text: &str, include_seperators: bool) -> VecDeque<Token> {
+/// Include seperators will ensure no character of text is lost. Math is whether math expressions are evaluated, in which case they are a single token
+fn parse_text_to_tokens(text: &str, include_seperators: bool, math: bool) -> VecDeque<Token> {
     // i hate this code
     // too much logic
     let mut tokens = VecDeque::new();
     // if input is simply a math expression, return it
-    let eval_result = try_eval(text);
-    if eval_result.is_ok() {
+    if math && try_eval(text).is_ok() {
         tokens.push_back(Token {
             text: text.to_string(),
             ty: TokenType::Keyword,
@@ -208,6 +229,201 @@ fn move_cursor_to_cursor_pos(original_x: usize, pos: usize, width: usize) -> io:
     Ok(())
 }
 
+/// Whether an argument looks like a path, i.e. contains a path seperator or starts with ~ or .
+fn looks_like_path(text: &str) -> bool {
+    text.contains(['/', '\\']) || text.starts_with(['~', '.'])
+}
+
+/// Operators that seperate commands, or redirect their input or output
+const OPERATORS: &[&str] = &[";", "&", "&&", "||", "|", ">", ">>", "<"];
+const REDIRECTIONS: &[&str] = &[">", ">>", "<"];
+
+/// Indices of tokens that would stop the input from running: unknown operators, redirections without a command or target,
+/// and a quote that is never closed
+fn find_input_problems(tokens: &VecDeque<Token>) -> Vec<usize> {
+    let mut problems = Vec::new();
+    let mut has_command = false;
+    for (index, token) in tokens.iter().enumerate() {
+        match token.ty {
+            TokenType::Special => {
+                let operator = token.text.trim();
+                let is_redirection = REDIRECTIONS.contains(&operator);
+                // the target can still be typed, but not if another operator comes first
+                let missing_target = is_redirection
+                    && tokens
+                        .iter()
+                        .skip(index + 1)
+                        .find(|next| !next.text.trim().is_empty())
+                        .is_some_and(|next| matches!(next.ty, TokenType::Special));
+                let missing_command = !has_command && operator != ";";
+                if !OPERATORS.contains(&operator) || missing_target || missing_command {
+                    problems.push(index);
+                }
+                if !is_redirection {
+                    has_command = false;
+                }
+            }
+            TokenType::Keyword => has_command = true,
+            _ => {}
+        }
+    }
+    // an unclosed quote is always the last token, since closing it starts a new one
+    if let Some(last) = tokens.back()
+        && matches!(last.ty, TokenType::QuotesArg)
+    {
+        problems.push(tokens.len() - 1);
+    }
+    problems
+}
+
+/// Whether an argument is a path to an existing file or directory
+fn path_exists(arg: &str) -> bool {
+    let path = arg.trim_matches('"').replace("\\ ", " ");
+    !path.is_empty() && Path::new(&shellexpand::tilde(&path).to_string()).exists()
+}
+
+/// Color input text by token, using the colors of the theme.
+///
+/// If `command_exists` is given, the input is also checked while it is typed. Unknown commands, unclosed quotes and bad operators
+/// are shown in the error color, and arguments that are existing paths are underlined.
+fn highlight_input(
+    text: &str,
+    theme: &Theme,
+    math: bool,
+    command_exists: Option<&dyn Fn(&str) -> bool>,
+) -> String {
+    let tokens = parse_text_to_tokens(text, true, math);
+    let problems = match command_exists {
+        Some(_) => find_input_problems(&tokens),
+        None => Vec::new(),
+    };
+    let mut highlighted = String::new();
+    // whether the next word is the target of a redirection, which is a path rather than a command
+    let mut is_target = false;
+    for (index, token) in tokens.iter().enumerate() {
+        let word = token.text.trim();
+        let ty = match token.ty {
+            TokenType::Keyword if is_target => TokenType::RegularArg,
+            _ => token.ty.clone(),
+        };
+        let mut color = match ty {
+            TokenType::Keyword => theme.primary_color,
+            TokenType::QuotesArg => theme.secondary_color,
+            TokenType::RegularArg => {
+                if token.text.starts_with("-") {
+                    theme.flag_color
+                } else if looks_like_path(&token.text) {
+                    theme.path_color
+                } else {
+                    theme.argument_color
+                }
+            }
+            TokenType::EnvironmentVariable => theme.primary_color,
+            TokenType::Special => theme.secondary_color,
+        };
+        let mut underline = false;
+        if let Some(command_exists) = command_exists {
+            let unknown_command =
+                matches!(ty, TokenType::Keyword) && !word.is_empty() && !command_exists(word);
+            if problems.contains(&index) || unknown_command {
+                color = theme.err_color;
+            }
+            underline = matches!(ty, TokenType::RegularArg | TokenType::QuotesArg)
+                && !word.starts_with('-')
+                && path_exists(word);
+        }
+        match token.ty {
+            TokenType::Special => is_target = REDIRECTIONS.contains(&word),
+            _ if !word.is_empty() => is_target = false,
+            _ => {}
+        }
+
+        highlighted += &SetForegroundColor(color).to_string();
+        if underline {
+            // don't underline the space after the path
+            let (path, rest) = token.text.split_at(token.text.trim_end().len());
+            highlighted += &SetAttribute(Attribute::Underlined).to_string();
+            highlighted += path;
+            highlighted += &SetAttribute(Attribute::NoUnderline).to_string();
+            highlighted += rest;
+        } else {
+            highlighted += &token.text;
+        }
+    }
+    highlighted
+}
+
+/// Show the characters from `start` to `end` of highlighted text in reverse video. Escape codes aren't counted as characters
+fn mark_selection(highlighted: &str, start: usize, end: usize) -> String {
+    let mut marked = String::new();
+    let mut chars = highlighted.chars().peekable();
+    let mut index = 0;
+    loop {
+        // copy escape codes as they are
+        while chars.peek() == Some(&'\x1b') {
+            for char in chars.by_ref() {
+                marked.push(char);
+                if char.is_ascii_alphabetic() {
+                    break;
+                }
+            }
+        }
+        if index == start {
+            marked += &SetAttribute(Attribute::Reverse).to_string();
+        }
+        if index == end {
+            marked += &SetAttribute(Attribute::NoReverse).to_string();
+        }
+        let Some(char) = chars.next() else {
+            break;
+        };
+        marked.push(char);
+        index += 1;
+    }
+    marked
+}
+
+/// Join the lines of text to one line of commands run one after another
+fn join_lines(text: &str) -> String {
+    text.lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .collect::<Vec<&str>>()
+        .join("; ")
+}
+
+/// Turn text from an editor or the clipboard into input. A single line is kept as it is, and multiple lines are joined
+fn text_to_input(text: &str) -> String {
+    // editors always end the file with a line break, and copied lines often have one too
+    let text = match text.strip_suffix('\n') {
+        Some(text) => text.strip_suffix('\r').unwrap_or(text),
+        None => text,
+    };
+    if text.contains('\n') {
+        join_lines(text)
+    } else {
+        text.to_string()
+    }
+}
+
+/// Create a new file in the temp dir. The name is random, so nothing else can guess it and create it first
+fn create_temp_file() -> io::Result<(PathBuf, std::fs::File)> {
+    loop {
+        let random = RandomState::new().build_hasher().finish();
+        let name = format!("shoe-{}-{:016x}.txt", std::process::id(), random);
+        let path = env::temp_dir().join(name);
+        match std::fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&path)
+        {
+            Ok(file) => return Ok((path, file)),
+            Err(error) if error.kind() == io::ErrorKind::AlreadyExists => continue,
+            Err(error) => return Err(error),
+        }
+    }
+}
+
 fn move_back_to(original_x: usize, steps: usize, width: usize) -> io::Result<()> {
     let rows = (original_x + steps) / width;
     if rows > 0 {
@@ -252,7 +468,11 @@ where
     }
 }
 
-fn filter_tokens_and_parse_vars(tokens: VecDeque<Token>) -> VecDeque<Token> {
+/// Join tokens and replace variables with their values. Variables set in shoe (with `export`) are looked up first, then the enviroment
+fn filter_tokens_and_parse_vars(
+    tokens: VecDeque<Token>,
+    variables: &HashMap<String, String>,
+) -> VecDeque<Token> {
     let mut new: VecDeque<Token> = VecDeque::new();
 
     let mut join = false;
@@ -260,7 +480,10 @@ fn filter_tokens_and_parse_vars(tokens: VecDeque<Token>) -> VecDeque<Token> {
 
     for mut token in tokens {
         if let TokenType::EnvironmentVariable = token.ty.clone() {
-            token.text = env::var(token.text).map_or(String::new(), |f| f.to_owned());
+            token.text = match variables.get(&token.text) {
+                Some(value) => value.clone(),
+                None => env::var(token.text).unwrap_or_default(),
+            };
             if last_was_empty {
                 new.push_back(Token {
                     text: String::new(),
@@ -387,7 +610,11 @@ fn absolute_pathbuf_to_string(input: &Path) -> String {
 }
 
 /// Autocomplete an input word to a relative or absolute path
-fn autocomplete_path(current_word: &String, mut item_index: usize) -> Option<String> {
+fn autocomplete_path(
+    current_word: &String,
+    mut item_index: usize,
+    ignore_case: bool,
+) -> Option<String> {
     // stores all found valid entries
     // in tuple where first element is whether the item is a directory, and the second is the path
     let mut valid: Vec<(bool, AbsoluteOrRelativePathBuf)> = Vec::new();
@@ -399,11 +626,11 @@ fn autocomplete_path(current_word: &String, mut item_index: usize) -> Option<Str
         let absolute_parent = path.parent()?;
         let contents = list_dir(absolute_parent).ok()?;
 
-        if env::consts::OS == "windows" {
+        if ignore_case {
             file_name = file_name.to_lowercase()
         };
         for (is_dir, item) in contents {
-            let item_in_maybe_lowercase = if env::consts::OS == "windows" {
+            let item_in_maybe_lowercase = if ignore_case {
                 item.to_lowercase()
             } else {
                 item.clone()
@@ -424,7 +651,7 @@ fn autocomplete_path(current_word: &String, mut item_index: usize) -> Option<Str
             path = RelativePathBuf::from(current_word);
             cwd = std::env::current_dir().ok()?;
         };
-        let file_name = if env::consts::OS == "windows" {
+        let file_name = if ignore_case {
             path.file_name()?.to_lowercase()
         } else {
             path.file_name()?.to_string()
@@ -437,7 +664,7 @@ fn autocomplete_path(current_word: &String, mut item_index: usize) -> Option<Str
 
         let contents = list_dir(absolute_parent).ok()?;
         for (is_dir, item) in contents {
-            let item_in_maybe_lowercase = if env::consts::OS == "windows" {
+            let item_in_maybe_lowercase = if ignore_case {
                 item.to_lowercase()
             } else {
                 item.clone()
@@ -468,9 +695,10 @@ fn autocomplete_keyword(
     current_word: &String,
     item_index: usize,
     path_executables: &Vec<String>,
+    ignore_case: bool,
 ) -> Option<String> {
     // first try autocompleting the input as a path
-    let autocompleted_path = autocomplete_path(current_word, item_index);
+    let autocompleted_path = autocomplete_path(current_word, item_index, ignore_case);
     if autocompleted_path.is_some() {
         return autocompleted_path;
     }
@@ -488,6 +716,34 @@ fn autocomplete_keyword(
     }
     None
 }
+/// The theme used when the config doesn't pick one. Debug builds use the debug theme, so they're easy to tell apart from release builds
+fn default_theme() -> Theme {
+    if cfg!(debug_assertions) {
+        DEBUG_THEME.clone()
+    } else {
+        THEMES[0].clone()
+    }
+}
+/// The theme to use for a config. Debug builds always use the debug theme, so they can be told apart from release builds
+fn config_theme(config: &Config) -> Theme {
+    if cfg!(debug_assertions) {
+        return default_theme();
+    }
+    // the config was checked when loaded, so the theme exists
+    config.theme().ok().flatten().unwrap_or_else(default_theme)
+}
+/// Characters that separate words, for moving and deleting a word at a time
+const DELETE_BREAK_CHARS: &[char] = &[' ', '/', '.', '-'];
+
+/// Get the first word of a string, including any leading whitespace
+fn next_word(text: &str) -> &str {
+    let start = text.len() - text.trim_start().len();
+    let end = text[start..]
+        .find(char::is_whitespace)
+        .map_or(text.len(), |index| start + index);
+    &text[..end]
+}
+
 enum CommandInputModifier {
     /// Read command input from file
     ReadFrom(String),
@@ -502,6 +758,8 @@ enum CommandOutputModifier {
     WriteTo(String, bool),
     /// Command output has no modifier.
     Default,
+    /// Command output is collected, to be the output of the command that ran it (like find -exec).
+    Captured,
 }
 
 enum RunCondition {
@@ -518,45 +776,120 @@ struct Command<'a> {
     run_condition: RunCondition,
 }
 struct Shoe {
-    history_path: Option<String>,
-    history: Vec<String>,
+    history_file: Option<HistoryFile>,
+    history: Vec<HistoryEntry>,
+    /// Whether to pick up commands run in other sessions while running
+    share_history: bool,
+    /// Commands matching any of these are never stored in history
+    history_ignore: Vec<IgnorePattern>,
+    /// Like `history_ignore`, but from the config file
+    config_history_ignore: Vec<IgnorePattern>,
     history_index: usize,
+    /// The text that was typed before stepping through history with Up/Down, which entries are filtered by
+    history_prefix: Option<String>,
     path_items: HashMap<String, PathBuf>,
     path_executables: Vec<String>,
     path_extensions: Vec<String>,
-    theme: &'static Theme,
+    theme: Theme,
     running: bool,
     listening: bool,
-    use_suggestions: bool,
-    substitute_tildes: bool,
+    /// Whether the command being run was entered by the user, and not run by the rc file or -c
+    interactive: bool,
+    config: Config,
+    /// What keys do in the line editor
+    keymap: KeyMap,
+    /// Text deleted with the kill actions, for yanking back
+    kill_buffer: String,
+    /// Whether the line editor is in vi normal mode, and not insert mode
+    vi_normal_mode: bool,
+    /// A vi operator waiting for a motion, like the `d` of `dw`
+    vi_pending: Option<char>,
+    /// Where the selection started, if text is selected. The cursor is at the other end
+    selection_anchor: Option<usize>,
     input_text: String,
     cursor_pos: usize,
     autocomplete_cycle_index: Option<usize>,
     last_input_before_autocomplete: Option<String>,
     enviroment_variables: HashMap<String, String>,
+    /// Exit code of the last command that was run
+    last_exit_code: i32,
+    /// The directory before the last directory change, used by `cd -`
+    previous_directory: Option<PathBuf>,
+    /// Directories pushed by `pushd`, most recent last
+    directory_stack: Vec<PathBuf>,
+    /// Visited directories, for jumping to with `z`
+    directory_database: Option<DirectoryDatabase>,
+    prompt: PromptTemplate,
+    right_prompt: Option<PromptTemplate>,
+    transient_prompt: Option<PromptTemplate>,
+    /// Width of the last line of the prompt as it was last printed, which is where input starts
+    prompt_width: usize,
+    /// Lines of the prompt as it was last printed, above the one input starts on
+    prompt_extra_lines: usize,
+    /// The right prompt as it was rendered when the prompt was last printed, so `update` can redraw it
+    rendered_right_prompt: Option<RenderedPrompt>,
+    /// How long the last command took to run, in milliseconds
+    last_duration: Option<u64>,
+    /// Git status of recently visited repos, for the prompt
+    git_cache: GitCache,
 }
 
 impl Shoe {
-    fn new(history_path: Option<String>) -> Self {
-        let history: Vec<String>;
-        if let Some(history_path) = &history_path {
-            let history_text =
-                std::fs::read_to_string(history_path).expect("Couldn't read ~/.shoehistory");
-            history = history_text
-                .split('\n')
-                .filter_map(|line| {
-                    if line.trim().is_empty() {
-                        None
-                    } else {
-                        Option::<String>::Some(line.to_string())
-                    }
-                })
-                .collect();
+    fn new(history_path: Option<String>, share_history: bool, config: Config) -> Self {
+        let mut history_file: Option<HistoryFile>;
+        let mut history: Vec<HistoryEntry>;
+        if let Some(history_path) = history_path {
+            let (file, entries) =
+                HistoryFile::open(history_path).expect("Couldn't read ~/.shoehistory");
+            history_file = Some(file);
+            history = entries;
         } else {
+            history_file = None;
             history = Vec::new();
         }
+        // only keep the most recent commands, if the history size is limited
+        let history_size = config.history.size;
+        if history_size > 0
+            && history.len() > history_size
+            && let Some(history_file) = &mut history_file
+        {
+            match history_file.trim(history_size) {
+                Ok(entries) => history = entries,
+                Err(error) => {
+                    queue!(stdout(), SetForegroundColor(utils::DEFAULT_ERR_COLOR)).unwrap();
+                    println!("Couldn't trim ~/.shoehistory: {}", error);
+                    queue!(stdout(), SetForegroundColor(Color::Reset)).unwrap();
+                }
+            }
+        }
         let history_index = history.len();
 
+        let history_ignore = if history_file.is_some() {
+            let ignore_path = shellexpand::tilde("~/.shoehistoryignore").to_string();
+            history::load_ignore_patterns(&ignore_path).unwrap_or_else(|error| {
+                queue!(stdout(), SetForegroundColor(utils::DEFAULT_ERR_COLOR)).unwrap();
+                println!("{}", error);
+                queue!(stdout(), SetForegroundColor(Color::Reset)).unwrap();
+                Vec::new()
+            })
+        } else {
+            Vec::new()
+        };
+
+        // the directory database is a companion to the history file, so is only used when history is
+        let directory_database = if history_file.is_some() {
+            let database_path = shellexpand::tilde("~/.shoez").to_string();
+            DirectoryDatabase::open(database_path)
+                .inspect_err(|error| {
+                    queue!(stdout(), SetForegroundColor(utils::DEFAULT_ERR_COLOR)).unwrap();
+                    println!("Couldn't read ~/.shoez: {}", error);
+                    queue!(stdout(), SetForegroundColor(Color::Reset)).unwrap();
+                })
+                .ok()
+        } else {
+            None
+        };
+
         let path_extensions = binaryfinder::get_path_extensions();
         let path_items = binaryfinder::get_items_in_path();
 
@@ -584,34 +917,148 @@ impl Shoe {
 
         let path_executables = path_item_names;
 
-        let theme;
-        #[cfg(debug_assertions)]
-        {
-            theme = &DEBUG_THEME;
-        }
-        #[cfg(not(debug_assertions))]
-        {
-            theme = &THEMES[0];
-        }
+        let theme = config_theme(&config);
 
         Shoe {
-            history_path,
+            history_file,
             history,
+            share_history,
+            history_ignore,
+            config_history_ignore: config.ignore_patterns().unwrap_or_default(),
             history_index,
+            history_prefix: None,
             path_items,
             path_executables,
             path_extensions,
             theme,
             running: false,
             listening: false,
-            use_suggestions: true,
-            substitute_tildes: true,
+            interactive: false,
+            keymap: config.keymap().unwrap_or_default(),
+            kill_buffer: String::new(),
+            vi_normal_mode: false,
+            vi_pending: None,
+            selection_anchor: None,
             input_text: String::new(),
             cursor_pos: 0,
             last_input_before_autocomplete: None,
             autocomplete_cycle_index: None,
             enviroment_variables: HashMap::new(),
+            last_exit_code: 0,
+            previous_directory: None,
+            directory_stack: Vec::new(),
+            directory_database,
+            prompt: config
+                .prompt(PromptKind::Main)
+                .ok()
+                .flatten()
+                .unwrap_or_default(),
+            right_prompt: config.prompt(PromptKind::Right).ok().flatten(),
+            transient_prompt: config.prompt(PromptKind::Transient).ok().flatten(),
+            config,
+            prompt_width: 0,
+            prompt_extra_lines: 0,
+            rendered_right_prompt: None,
+            last_duration: None,
+            git_cache: GitCache::default(),
+        }
+    }
+    /// Use new settings from the config file.
+    ///
+    /// The theme and prompts are only changed if their settings changed, so ones set with `theme` and `prompt` are kept otherwise
+    fn apply_config(&mut self, config: Config) {
+        // the config was checked when loaded, so none of these fail
+        if config.theme != self.config.theme {
+            self.theme = config_theme(&config);
+        }
+        if config.prompt != self.config.prompt {
+            self.prompt = config
+                .prompt(PromptKind::Main)
+                .ok()
+                .flatten()
+                .unwrap_or_default();
+            self.right_prompt = config.prompt(PromptKind::Right).ok().flatten();
+            self.transient_prompt = config.prompt(PromptKind::Transient).ok().flatten();
+        }
+        self.config_history_ignore = config.ignore_patterns().unwrap_or_default();
+        self.keymap = config.keymap().unwrap_or_default();
+        self.config = config;
+        self.vi_normal_mode = false;
+    }
+    /// Autocomplete an argument to `z` to a full path of a matching visited directory
+    fn autocomplete_visited_directory(
+        &self,
+        current_word: &str,
+        item_index: usize,
+    ) -> Option<String> {
+        let database = self.directory_database.as_ref()?;
+        let matches = frecency::find_matches(&database.directories, &[current_word.trim()]);
+        if matches.is_empty() {
+            return None;
         }
+        Some(matches[item_index % matches.len()].path.clone())
+    }
+    /// Change the working directory, remembering the previous one
+    fn change_directory(&mut self, path: &Path) -> io::Result<()> {
+        let previous = std::env::current_dir()?;
+        std::env::set_current_dir(path)?;
+        self.previous_directory = Some(previous);
+        if let Some(database) = &mut self.directory_database {
+            let current = std::env::current_dir()?;
+            // failing to record the visit shouldn't make the cd fail
+            let _ = database.record(&current.to_string_lossy());
+        }
+        Ok(())
+    }
+    /// Render a prompt template with the current values. None if there is no template of that kind
+    fn render_prompt(&mut self, kind: PromptKind) -> io::Result<Option<RenderedPrompt>> {
+        let template = match kind {
+            PromptKind::Main => &self.prompt,
+            PromptKind::Right => match &self.right_prompt {
+                Some(template) => template,
+                None => return Ok(None),
+            },
+            PromptKind::Transient => match &self.transient_prompt {
+                Some(template) => template,
+                None => return Ok(None),
+            },
+        };
+        let cwd = self.cwd_to_str()?;
+        // reading git status takes some work, so only do it if it's shown
+        let git_status = if template.uses(&[Placeholder::Git, Placeholder::Branch]) {
+            self.git_cache.status(&std::env::current_dir()?)
+        } else {
+            None
+        };
+        let vi_mode = self.config.vi_mode();
+        let rendered = template.render(&self.theme, |placeholder| match placeholder {
+            Placeholder::Cwd => cwd.clone(),
+            Placeholder::ShortCwd => match cwd.rsplit('/').next() {
+                Some(last) if !last.is_empty() => last.to_string(),
+                _ => cwd.clone(),
+            },
+            Placeholder::User => env::var("USER")
+                .or_else(|_| env::var("USERNAME"))
+                .unwrap_or_default(),
+            Placeholder::Host => gethostname::gethostname().to_string_lossy().to_string(),
+            Placeholder::Time => chrono::Local::now().format("%H:%M:%S").to_string(),
+            Placeholder::Status => self.last_exit_code.to_string(),
+            Placeholder::Duration => self.last_duration.map(format_duration).unwrap_or_default(),
+            Placeholder::Git => git_status
+                .as_ref()
+                .map(GitStatus::segment)
+                .unwrap_or_default(),
+            Placeholder::Branch => git_status
+                .as_ref()
+                .map(|status| status.branch.clone())
+                .unwrap_or_default(),
+            // shoe doesn't run commands in the background, so there are never any jobs
+            Placeholder::Jobs => String::from("0"),
+            Placeholder::ViMode if !vi_mode => String::new(),
+            Placeholder::ViMode if self.vi_normal_mode => String::from("[N] "),
+            Placeholder::ViMode => String::from("[I] "),
+        });
+        Ok(Some(rendered))
     }
     /// Convert cwd to a string, also replacing home path with ~
     fn cwd_to_str(&self) -> io::Result<String> {
@@ -637,9 +1084,11 @@ impl Shoe {
             Ok(path_string.replace(&home_path, "~"))
         }
     }
-    fn execute_commands(&mut self, commands: Vec<Command>) -> io::Result<()> {
+    /// Run parsed commands. Returns the output of commands whose output is captured
+    fn execute_commands(&mut self, commands: Vec<Command>) -> io::Result<Vec<u8>> {
         // if command has output piped to the next, store the output here
         let mut last_piped_output: Option<Vec<u8>> = None;
+        let mut captured_output = Vec::new();
         // if last command succeeeded
         let mut last_success: Option<bool> = None;
 
@@ -691,46 +1140,75 @@ impl Shoe {
             let mut output_buf = Vec::new();
             let mut context = CommandContext {
                 args: &command.args,
-                theme: self.theme,
+                theme: &self.theme,
+                config: &self.config,
                 stdout: &mut output_buf,
                 stdin: stdin_data.clone().unwrap_or_default(),
                 path_items: &self.path_items,
                 path_extensions: &self.path_extensions,
+                history: &self.history,
+                enviroment_variables: &self.enviroment_variables,
+                output_is_terminal: matches!(output_modifier, CommandOutputModifier::Default),
+                raw_output: false,
+                previous_directory: self.previous_directory.as_ref(),
+                directory_stack: &self.directory_stack,
+                visited_directories: self
+                    .directory_database
+                    .as_ref()
+                    .map_or(&[], |database| &database.directories),
             };
             let result = commands::execute_command(&command.keyword, &mut context);
+            let raw_output = context.raw_output;
             let mut not_a_builtin_command = false;
 
-            last_success = Some(result.is_ok());
+            let failed = matches!(result, Err(_) | Ok(commands::CommandResult::Failed));
+            last_success = Some(!failed);
+            self.last_exit_code = if failed { 1 } else { 0 };
             match result {
                 Ok(result) => {
                     match result {
                         commands::CommandResult::Exit => {
                             self.listening = false;
                             self.running = false;
-                            return Ok(());
+                            return Ok(captured_output);
                         }
-                        #[allow(unused)]
-                        commands::CommandResult::UpdateTheme(new_index) => {
-                            #[cfg(not(debug_assertions))]
-                            {
-                                self.theme = &THEMES[new_index];
+                        commands::CommandResult::UpdateTheme(new_theme) => {
+                            let name = new_theme.name.clone();
+                            self.theme = new_theme;
+                            // themes picked while testing debug builds shouldn't change the theme of release builds,
+                            // and themes set by the rc file are set again every time anyway
+                            if !cfg!(debug_assertions) && self.interactive {
+                                match config::set("theme", &[&name]) {
+                                    Ok(config) => self.apply_config(config),
+                                    Err(error) => {
+                                        queue!(stdout(), SetForegroundColor(self.theme.err_color))?;
+                                        println!("Couldn't save the theme: {}", error);
+                                        queue!(stdout(), SetForegroundColor(Color::Reset))?;
+                                    }
+                                }
                             }
                         }
-                        commands::CommandResult::Lovely => {
-                            let output_utf8 = String::from_utf8_lossy(&output_buf);
+                        commands::CommandResult::UpdateConfig(config) => {
+                            self.apply_config(config);
+                        }
+                        commands::CommandResult::Lovely | commands::CommandResult::Failed => {
                             if let CommandOutputModifier::Default = output_modifier {
                                 // write output
-                                write!(stdout(), "{output_utf8}")?;
+                                stdout().write_all(&output_buf)?;
                             }
 
                             let is_utf8 = std::str::from_utf8(&output_buf).is_ok();
 
-                            // strip output_buf of ansi escape codes if it contains an ansi escape code, it is utf-8, and the output is redirected to a file
-                            if matches!(
-                                command.output_modifier,
-                                CommandOutputModifier::WriteTo(_, _)
-                            ) && is_utf8
-                                && output_utf8.contains('\x1B')
+                            // strip output_buf of ansi escape codes if it contains an ansi escape code, it is utf-8, and the output is redirected to a file.
+                            // raw output (like from cat) is always kept as is
+                            if !raw_output
+                                && matches!(
+                                    command.output_modifier,
+                                    CommandOutputModifier::WriteTo(_, _)
+                                        | CommandOutputModifier::Captured
+                                )
+                                && is_utf8
+                                && output_buf.contains(&b'\x1B')
                             {
                                 output_buf = strip_ansi_escapes::strip(output_buf);
                             }
@@ -743,12 +1221,85 @@ impl Shoe {
                         commands::CommandResult::SetEnvVar(key, value) => {
                             self.enviroment_variables.insert(key, value);
                         }
+                        commands::CommandResult::DeleteHistoryEntry(index) => {
+                            let entry = self.history.remove(index);
+                            if let Some(history_file) = &mut self.history_file {
+                                let entries = history_file.delete(index, &entry)?;
+                                self.merge_history(NewEntries::Rewritten(entries));
+                            }
+                            self.history_index = self.history.len();
+                        }
+                        commands::CommandResult::ClearHistory => {
+                            self.history.clear();
+                            if let Some(history_file) = &mut self.history_file {
+                                history_file.clear()?;
+                            }
+                            self.history_index = self.history.len();
+                        }
+                        commands::CommandResult::ChangeDirectory(path) => {
+                            self.change_directory(&path)?;
+                        }
+                        commands::CommandResult::PushDirectory(path) => {
+                            let current = std::env::current_dir()?;
+                            self.change_directory(&path)?;
+                            self.directory_stack.push(current);
+                        }
+                        commands::CommandResult::PopDirectory => {
+                            // only remove it from the stack once it has been changed to
+                            if let Some(path) = self.directory_stack.last().cloned() {
+                                self.change_directory(&path)?;
+                                self.directory_stack.pop();
+                            }
+                        }
+                        commands::CommandResult::SwapDirectory => {
+                            if let Some(path) = self.directory_stack.last().cloned() {
+                                let current = std::env::current_dir()?;
+                                self.change_directory(&path)?;
+                                if let Some(top) = self.directory_stack.last_mut() {
+                                    *top = current;
+                                }
+                            }
+                        }
+                        commands::CommandResult::SetPrompt(kind, template) => match kind {
+                            PromptKind::Main => self.prompt = template.unwrap_or_default(),
+                            PromptKind::Right => self.right_prompt = template,
+                            PromptKind::Transient => self.transient_prompt = template,
+                        },
+                        commands::CommandResult::RunCommands(commands) => {
+                            let was_running = self.running;
+                            // if the output is piped or redirected, so is the output of the commands
+                            let capture_output =
+                                !matches!(output_modifier, CommandOutputModifier::Default);
+                            let mut output = Vec::new();
+                            for command in commands {
+                                output.extend(self.run_command_string(&command, capture_output)?);
+                                // stop if one of the commands was exit
+                                if was_running && !self.running {
+                                    return Ok(captured_output);
+                                }
+                            }
+                            if capture_output {
+                                stdout_data = Some(output);
+                            }
+                        }
                     }
                 }
                 Err(error) => {
+                    // output written before the error is still kept, like the files cat could read
+                    let partial_output = !output_buf.is_empty();
+                    if partial_output && let CommandOutputModifier::Default = output_modifier {
+                        stdout().write_all(&output_buf)?;
+                        if !output_buf.ends_with(b"\n") {
+                            println!();
+                        }
+                    }
                     queue!(stdout(), SetForegroundColor(self.theme.err_color))?;
                     println!("{}", error);
-                    continue;
+                    if !partial_output {
+                        continue;
+                    }
+                    queue!(stdout(), SetForegroundColor(Color::Reset))?;
+                    stdout_data = Some(output_buf);
                 }
             }
 
@@ -789,6 +1340,7 @@ impl Shoe {
                 // if process cant be spawned
                 let Ok(mut process) = process.spawn() else {
                     last_success = Some(false);
+                    self.last_exit_code = 127;
                     queue!(stdout(), SetForegroundColor(self.theme.err_color)).unwrap();
                     println!("file/command '{}' not found! :(", keyword);
                     continue;
@@ -799,7 +1351,9 @@ impl Shoe {
                         stdin.write_all(&buf)?;
                     }
                 }
-                let success = process.wait()?.success();
+                let status = process.wait()?;
+                let success = status.success();
+                self.last_exit_code = status.code().unwrap_or(1);
 
                 // if process has readable stdout, strip it from ansi codes and store here
                 let stripped_output: Option<Vec<u8>> = if let Some(stdout) = &mut process.stdout {
@@ -841,10 +1395,39 @@ impl Shoe {
                         println!();
                     }
                 }
+                CommandOutputModifier::Captured => {
+                    if let Some(stdout_data) = stdout_data {
+                        captured_output.extend(stdout_data);
+                    }
+                }
+            }
+        }
+        Ok(captured_output)
+    }
+    /// Add the entries other sessions have written to the history file, if history is shared.
+    ///
+    /// The history file going missing or being locked shouldn't stop the shell, so errors are only printed
+    fn merge_history_result(&mut self, new_entries: io::Result<NewEntries>) -> io::Result<()> {
+        match new_entries {
+            Ok(new_entries) => self.merge_history(new_entries),
+            Err(error) => {
+                queue!(stderr(), SetForegroundColor(self.theme.err_color))?;
+                eprintln!("Couldn't update the history file: {}", error);
+                queue!(stderr(), SetForegroundColor(Color::Reset))?;
             }
         }
         Ok(())
     }
+    /// Add the entries other sessions have written to the history file, if history is shared
+    fn merge_history(&mut self, new_entries: NewEntries) {
+        if !self.share_history {
+            return;
+        }
+        match new_entries {
+            NewEntries::Appended(entries) => self.history.extend(entries),
+            NewEntries::Rewritten(entries) => self.history = entries,
+        }
+    }
     fn write_char(&mut self, new_char: char) {
         if self.input_text.chars().count() == self.cursor_pos {
             self.input_text.insert(self.input_text.len(), new_char);
@@ -873,7 +1456,7 @@ impl Shoe {
     }
     fn get_word_at_cursor(&self) -> Option<(usize, Token)> {
         let mut counter = 0;
-        for (index, token) in parse_text_to_tokens(&self.input_text, false)
+        for (index, token) in parse_text_to_tokens(&self.input_text, false, self.config.math)
             .into_iter()
             .enumerate()
         {
@@ -884,230 +1467,577 @@ impl Shoe {
         }
         None
     }
+    /// Position of the start of the word before the cursor. Words are separated by [DELETE_BREAK_CHARS]
+    fn previous_word_boundary(&self) -> usize {
+        let chars: Vec<char> = self.input_text.chars().collect();
+        let mut pos = self.cursor_pos.min(chars.len());
+        while pos > 0 && DELETE_BREAK_CHARS.contains(&chars[pos - 1]) {
+            pos -= 1;
+        }
+        while pos > 0 && !DELETE_BREAK_CHARS.contains(&chars[pos - 1]) {
+            pos -= 1;
+        }
+        pos
+    }
+    /// Position of the end of the word after the cursor. Words are separated by [DELETE_BREAK_CHARS]
+    fn next_word_boundary(&self) -> usize {
+        let chars: Vec<char> = self.input_text.chars().collect();
+        let mut pos = self.cursor_pos.min(chars.len());
+        while pos < chars.len() && DELETE_BREAK_CHARS.contains(&chars[pos]) {
+            pos += 1;
+        }
+        while pos < chars.len() && !DELETE_BREAK_CHARS.contains(&chars[pos]) {
+            pos += 1;
+        }
+        pos
+    }
+    /// Delete the characters from `start` to `end`, keeping them so they can be yanked back
+    fn kill(&mut self, start: usize, end: usize) {
+        if start >= end {
+            return;
+        }
+        let chars: Vec<char> = self.input_text.chars().collect();
+        self.kill_buffer = chars[start..end].iter().collect();
+        self.input_text = chars[..start].iter().chain(&chars[end..]).collect();
+        self.cursor_pos = start;
+    }
+    /// Start and end of the selected text, if any
+    fn selection(&self) -> Option<(usize, usize)> {
+        let anchor = self.selection_anchor?;
+        let start = anchor.min(self.cursor_pos);
+        let end = anchor
+            .max(self.cursor_pos)
+            .min(self.input_text.chars().count());
+        (start < end).then_some((start, end))
+    }
+    fn selected_text(&self) -> Option<String> {
+        let (start, end) = self.selection()?;
+        Some(
+            self.input_text
+                .chars()
+                .skip(start)
+                .take(end - start)
+                .collect(),
+        )
+    }
+    /// Delete the selected text. Returns whether there was any
+    fn delete_selection(&mut self) -> bool {
+        let Some((start, end)) = self.selection() else {
+            return false;
+        };
+        let chars: Vec<char> = self.input_text.chars().collect();
+        self.input_text = chars[..start].iter().chain(&chars[end..]).collect();
+        self.cursor_pos = start;
+        self.selection_anchor = None;
+        true
+    }
+    /// Copy the selected text to the clipboard, like the `copy` builtin does
+    fn copy_selection(&self) {
+        let Some(text) = self.selected_text() else {
+            return;
+        };
+        // there may not be a clipboard (like over ssh), which there's no good way to show while typing
+        if let Ok(mut context) = copypasta::ClipboardContext::new() {
+            let _ = context.set_contents(text);
+        }
+    }
+    fn insert_text(&mut self, text: &str) {
+        for char in text.chars() {
+            self.write_char(char);
+            self.cursor_pos += 1;
+        }
+    }
+    /// Fill in the next word of the suggestion
+    fn accept_suggestion_word(&mut self) {
+        if let Some(suggestion) = self.get_suggestion() {
+            self.input_text += next_word(&suggestion[self.input_text.len()..]);
+        }
+        self.cursor_pos = self.input_text.chars().count();
+    }
+    /// Print the prompt again, like after the screen was cleared or the vi mode changed. The cursor has to be at the start of the input
+    fn redraw_prompt(&mut self) -> io::Result<()> {
+        if self.prompt_extra_lines > 0 {
+            queue!(stdout(), MoveUp(self.prompt_extra_lines as u16))?;
+        }
+        queue!(stdout(), MoveToColumn(0), Clear(ClearType::FromCursorDown))?;
+        let prompt = self.render_prompt(PromptKind::Main)?.unwrap();
+        self.prompt_width = prompt.width;
+        self.prompt_extra_lines = prompt.extra_lines;
+        print!("{}", prompt.text);
+        self.rendered_right_prompt = self.render_prompt(PromptKind::Right)?;
+        Ok(())
+    }
+    /// Edit the input in the editor set in $VISUAL or $EDITOR. Lines are joined with `;`, so they run one after another
+    fn open_in_editor(&mut self) -> io::Result<()> {
+        let editor = ["VISUAL", "EDITOR"]
+            .iter()
+            .find_map(|name| {
+                self.enviroment_variables
+                    .get(*name)
+                    .cloned()
+                    .or_else(|| env::var(name).ok())
+                    .filter(|value| !value.trim().is_empty())
+            })
+            .unwrap_or_else(|| {
+                if env::consts::OS == "windows" {
+                    String::from("notepad")
+                } else {
+                    String::from("vi")
+                }
+            });
+        // the editor can have args, like `code --wait`
+        let mut parts = editor.split_whitespace();
+        let program = parts.next().unwrap_or_default();
+
+        let text = create_temp_file().and_then(|(path, mut file)| {
+            let text = file.write_all(self.input_text.as_bytes()).and_then(|_| {
+                // close the file before the editor opens it
+                drop(file);
+                disable_raw_mode()?;
+                let status = process::Command::new(program)
+                    .args(parts)
+                    .arg(&path)
+                    .status();
+                enable_raw_mode()?;
+                // editors exit with an error to cancel, like :cq in vim
+                let status = status?;
+                if !status.success() {
+                    return Err(io::Error::other(status.to_string()));
+                }
+                std::fs::read_to_string(&path)
+            });
+            let _ = std::fs::remove_file(&path);
+            text
+        });
+
+        match text {
+            Ok(text) => {
+                self.input_text = text_to_input(&text);
+                self.cursor_pos = self.input_text.chars().count();
+            }
+            Err(error) => {
+                // keep the input as it was. show the error on its own line, then the prompt again below it
+                queue!(stdout(), SetForegroundColor(self.theme.err_color))?;
+                print!("\r\nCouldn't edit with '{}': {}\r\n", program, error);
+                queue!(stdout(), SetForegroundColor(Color::Reset))?;
+                self.prompt_extra_lines = 0;
+            }
+        }
+        Ok(())
+    }
     fn handle_key_press(&mut self, event: Event) -> io::Result<()> {
         if let Event::Key(key_event) = event {
             if key_event.kind != KeyEventKind::Press {
                 return Ok(());
             }
             let mut reset_autocomplete_cycle = true;
-            match key_event.code {
-                KeyCode::Enter => {
-                    self.listening = false;
+            let mut reset_history_prefix = true;
+            // any key other than the select keys ends the selection
+            let mut keep_selection = false;
+            let vi_command = if self.vi_normal_mode {
+                keybindings::vi_normal_command(&key_event, self.vi_pending.take())
+            } else {
+                ViCommand::Default
+            };
+            let actions: Vec<Action> = match vi_command {
+                ViCommand::Actions(actions) => actions.to_vec(),
+                ViCommand::Operator(operator) => {
+                    self.vi_pending = Some(operator);
+                    Vec::new()
                 }
-                KeyCode::Char(char) => {
-                    if key_event.modifiers.contains(KeyModifiers::CONTROL) && char == 'c' {
-                        self.input_text = String::new();
+                ViCommand::Default => match self.keymap.action(&key_event) {
+                    Some(action) => vec![action],
+                    None => {
+                        // keys that aren't bound type their character. ctrl+alt is altgr on windows, which types too
+                        if let KeyCode::Char(char) = key_event.code
+                            && !self.vi_normal_mode
+                            && key_event.modifiers != KeyModifiers::CONTROL
+                            && key_event.modifiers != KeyModifiers::ALT
+                        {
+                            // typing replaces the selected text
+                            self.delete_selection();
+                            self.write_char(char);
+                            self.cursor_pos += 1;
+                        }
+                        Vec::new()
+                    }
+                },
+            };
+            for action in actions {
+                match action {
+                    Action::AcceptLine => {
                         self.listening = false;
-                    } else {
-                        self.write_char(char);
-                        self.cursor_pos += 1;
                     }
-                }
-                KeyCode::Esc => {
-                    self.input_text = String::new();
-                    self.cursor_pos = 0;
-                }
-                KeyCode::Tab => 'tab: {
-                    reset_autocomplete_cycle = false;
-                    if self.input_text.is_empty() {
-                        break 'tab;
+                    Action::Cancel => {
+                        self.input_text = String::new();
+                        self.listening = false;
                     }
-                    if let Some(last_input) = &self.last_input_before_autocomplete {
-                        self.cursor_pos -= self.input_text.len().saturating_sub(last_input.len());
-                        self.input_text = last_input.to_string();
-                        self.autocomplete_cycle_index =
-                            Some(self.autocomplete_cycle_index.unwrap() + 1);
-                    } else {
-                        self.autocomplete_cycle_index = Some(0);
-                        self.last_input_before_autocomplete = Some(self.input_text.to_string());
+                    Action::ClearLine => {
+                        self.input_text = String::new();
+                        self.cursor_pos = 0;
                     }
-                    let mut words = parse_text_to_tokens(&self.input_text, true);
-                    let Some((word_index, word)) = self.get_word_at_cursor() else {
-                        break 'tab;
-                    };
-                    let token_type = &words[word_index].ty;
-                    let is_keyword = matches!(token_type, TokenType::Keyword);
-
-                    // so we know if we need to strip before autocompletion and then re-add at the end
-                    // not all QuoteArgs end with quotes, as one isnt needed, so we need to check that it actually ends with one.
-                    let ends_with_quote = matches!(token_type, TokenType::QuotesArg)
-                        && words[word_index].text.ends_with('"');
-                    // all QuotesArgs will start with a quote
-                    let starts_with_quote = matches!(token_type, TokenType::QuotesArg);
-
-                    let ends_with_space = words[word_index].text.ends_with(' ');
-                    words.remove(word_index);
-
-                    // try complete path
-
-                    // if on keyword, autocomplete as keyword (i.e. also include executables from PATH)c
-                    let result = if is_keyword {
-                        autocomplete_keyword(
-                            &word.text,
-                            self.autocomplete_cycle_index.unwrap(),
-                            &self.path_executables,
-                        )
-                    } else {
-                        // if not on keyword, just autocomplete as path
-                        autocomplete_path(&word.text, self.autocomplete_cycle_index.unwrap())
-                    };
-
-                    let Some(mut autocompletion_string) = result else {
-                        break 'tab;
-                    };
-
-                    // enclose in quotes if the autocompletion has spaces, and the original text doesnt have quotes
-                    if autocompletion_string.contains(' ') && !starts_with_quote {
-                        autocompletion_string = String::from("\"") + &autocompletion_string;
+                    Action::Complete => 'tab: {
+                        reset_autocomplete_cycle = false;
+                        if self.input_text.is_empty() {
+                            break 'tab;
+                        }
+                        if let Some(last_input) = &self.last_input_before_autocomplete {
+                            self.cursor_pos -=
+                                self.input_text.len().saturating_sub(last_input.len());
+                            self.input_text = last_input.to_string();
+                            self.autocomplete_cycle_index =
+                                Some(self.autocomplete_cycle_index.unwrap() + 1);
+                        } else {
+                            self.autocomplete_cycle_index = Some(0);
+                            self.last_input_before_autocomplete = Some(self.input_text.to_string());
+                        }
+                        let mut words =
+                            parse_text_to_tokens(&self.input_text, true, self.config.math);
+                        let Some((word_index, word)) = self.get_word_at_cursor() else {
+                            break 'tab;
+                        };
+                        let token_type = &words[word_index].ty;
+                        let is_keyword = matches!(token_type, TokenType::Keyword);
+
+                        // so we know if we need to strip before autocompletion and then re-add at the end
+                        // not all QuoteArgs end with quotes, as one isnt needed, so we need to check that it actually ends with one.
+                        let ends_with_quote = matches!(token_type, TokenType::QuotesArg)
+                            && words[word_index].text.ends_with('"');
+                        // all QuotesArgs will start with a quote
+                        let starts_with_quote = matches!(token_type, TokenType::QuotesArg);
+
+                        let ends_with_space = words[word_index].text.ends_with(' ');
+                        // the keyword of the command the word is an argument to
+                        let command_keyword = words
+                            .iter()
+                            .take(word_index)
+                            .rev()
+                            .find(|token| matches!(token.ty, TokenType::Keyword))
+                            .map(|token| token.text.trim().to_string());
+                        let command_keyword = command_keyword.as_deref();
+                        words.remove(word_index);
+
+                        // try complete path
+
+                        // if on keyword, autocomplete as keyword (i.e. also include executables from PATH)c
+                        let result = if is_keyword {
+                            autocomplete_keyword(
+                                &word.text,
+                                self.autocomplete_cycle_index.unwrap(),
+                                &self.path_executables,
+                                self.config.completion.ignore_case,
+                            )
+                        } else if command_keyword == Some("z") {
+                            // args of z are completed from the visited directories
+                            self.autocomplete_visited_directory(
+                                &word.text,
+                                self.autocomplete_cycle_index.unwrap(),
+                            )
+                        } else {
+                            // if not on keyword, just autocomplete as path
+                            autocomplete_path(
+                                &word.text,
+                                self.autocomplete_cycle_index.unwrap(),
+                                self.config.completion.ignore_case,
+                            )
+                        };
+
+                        let Some(mut autocompletion_string) = result else {
+                            break 'tab;
+                        };
+
+                        // enclose in quotes if the autocompletion has spaces, and the original text doesnt have quotes
+                        if autocompletion_string.contains(' ') && !starts_with_quote {
+                            autocompletion_string = String::from("\"") + &autocompletion_string;
+
+                            // if this isnt the last word, also add end quote
+                            if word_index != words.len() {
+                                autocompletion_string += "\"";
+                            }
+                        }
 
-                        // if this isnt the last word, also add end quote
-                        if word_index != words.len() {
+                        self.cursor_pos +=
+                            autocompletion_string.chars().count() - word.text.chars().count();
+                        if starts_with_quote {
+                            autocompletion_string = String::from("\"") + &autocompletion_string;
+                        }
+                        if ends_with_quote {
                             autocompletion_string += "\"";
                         }
+                        if ends_with_space {
+                            autocompletion_string += " ";
+                        }
+                        let mut new = String::new();
+                        for (index, word) in words.iter().enumerate() {
+                            if word_index == index {
+                                new += &autocompletion_string;
+                            }
+                            new += &word.text;
+                        }
+                        if word_index == words.len() {
+                            new += &autocompletion_string;
+                        }
+                        self.input_text = new;
                     }
-
-                    self.cursor_pos +=
-                        autocompletion_string.chars().count() - word.text.chars().count();
-                    if starts_with_quote {
-                        autocompletion_string = String::from("\"") + &autocompletion_string;
-                    }
-                    if ends_with_quote {
-                        autocompletion_string += "\"";
+                    Action::ForwardChar => {
+                        self.cursor_pos += 1;
+                        if self.cursor_pos > self.input_text.chars().count() {
+                            // if we press right arrow at the last character, fill in suggestion
+                            if let Some(suggestion) = self.get_suggestion() {
+                                self.input_text = suggestion;
+                            }
+                            // move to last char
+                            self.cursor_pos = self.input_text.chars().count();
+                        }
                     }
-                    if ends_with_space {
-                        autocompletion_string += " ";
+                    Action::BackwardChar => {
+                        self.cursor_pos = self.cursor_pos.saturating_sub(1);
                     }
-                    let mut new = String::new();
-                    for (index, word) in words.iter().enumerate() {
-                        if word_index == index {
-                            new += &autocompletion_string;
+                    Action::ForwardWord => {
+                        if self.cursor_pos == self.input_text.chars().count() {
+                            self.accept_suggestion_word();
+                        } else {
+                            self.cursor_pos = self.next_word_boundary();
                         }
-                        new += &word.text;
                     }
-                    if word_index == words.len() {
-                        new += &autocompletion_string;
+                    Action::BackwardWord => {
+                        self.cursor_pos = self.previous_word_boundary();
                     }
-                    self.input_text = new;
-                }
-                KeyCode::Delete => {
-                    self.delete_char();
-                }
-                KeyCode::Backspace => {
-                    let ctrl = key_event.modifiers.contains(KeyModifiers::CONTROL);
-                    const DELETE_BREAK_CHARS: &[char] = &[' ', '/', '.', '-'];
-                    let mut delete_until_non_break_char = ctrl
-                        && DELETE_BREAK_CHARS.contains(
-                            &self
-                                .input_text
-                                .chars()
-                                .nth(self.cursor_pos.saturating_sub(1))
-                                .unwrap_or('p'),
-                        );
-
-                    // loop because ctrl+backspace will delete more than one character
-                    loop {
-                        if self.cursor_pos > 0 {
+                    Action::BeginningOfLine => {
+                        self.cursor_pos = 0;
+                    }
+                    Action::EndOfLine => {
+                        self.cursor_pos = self.input_text.chars().count();
+                    }
+                    Action::DeleteChar => {
+                        if !self.delete_selection() {
+                            self.delete_char();
+                        }
+                    }
+                    Action::BackwardDeleteChar => {
+                        if !self.delete_selection() && self.cursor_pos > 0 {
                             self.cursor_pos -= 1;
                             self.delete_char();
-
-                            if !ctrl {
-                                // always only delete one character if ctrl isnt pressed
-                                break;
-                            }
-                            // check if last char should stop ctrl+backspace delete
-                            let char = self
-                                .input_text
-                                .chars()
-                                .nth(self.cursor_pos.saturating_sub(1))
-                                .unwrap_or('p');
-                            let is_break_char = DELETE_BREAK_CHARS.contains(&char);
-                            if delete_until_non_break_char {
-                                if !is_break_char {
-                                    delete_until_non_break_char = false;
-                                }
-                            } else if is_break_char {
-                                break;
-                            }
-                        } else {
-                            break;
                         }
                     }
-                }
-                KeyCode::Up => {
-                    if !self.history.is_empty() {
-                        if self.history_index > 0 {
-                            self.history_index -= 1;
+                    Action::KillWord => {
+                        self.kill(self.cursor_pos, self.next_word_boundary());
+                    }
+                    Action::BackwardKillWord => {
+                        self.kill(self.previous_word_boundary(), self.cursor_pos);
+                    }
+                    Action::KillLine => {
+                        self.kill(self.cursor_pos, self.input_text.chars().count());
+                    }
+                    Action::BackwardKillLine => {
+                        self.kill(0, self.cursor_pos);
+                    }
+                    Action::KillWholeLine => {
+                        self.kill(0, self.input_text.chars().count());
+                    }
+                    Action::Yank => {
+                        self.delete_selection();
+                        self.insert_text(&self.kill_buffer.clone());
+                    }
+                    Action::ViPaste => {
+                        self.cursor_pos =
+                            (self.cursor_pos + 1).min(self.input_text.chars().count());
+                        self.insert_text(&self.kill_buffer.clone());
+                    }
+                    Action::TransposeChars => {
+                        let mut chars: Vec<char> = self.input_text.chars().collect();
+                        // at the end of the input, swap the last two characters
+                        let pos = self.cursor_pos.min(chars.len().saturating_sub(1));
+                        if pos > 0 {
+                            chars.swap(pos - 1, pos);
+                            self.input_text = chars.into_iter().collect();
+                            self.cursor_pos = pos + 1;
+                        }
+                    }
+                    Action::AcceptSuggestion => {
+                        if let Some(suggestion) = self.get_suggestion() {
+                            self.input_text = suggestion;
                         }
-                        self.input_text = self.history[self.history_index].clone();
                         self.cursor_pos = self.input_text.chars().count();
                     }
-                }
-                KeyCode::Down => {
-                    if !self.history.is_empty() {
-                        if self.history_index < self.history.len() {
-                            self.history_index += 1;
+                    Action::AcceptSuggestionWord => {
+                        if self.cursor_pos == self.input_text.chars().count() {
+                            self.accept_suggestion_word();
+                        } else {
+                            self.cursor_pos += 1;
                         }
-                        if self.history_index < self.history.len() {
-                            self.input_text = self.history[self.history_index].clone();
+                    }
+                    Action::HistoryPrevious => {
+                        reset_history_prefix = false;
+                        let prefix = self
+                            .history_prefix
+                            .get_or_insert_with(|| self.input_text.clone())
+                            .clone();
+                        let matches = self.history_matches(&prefix);
+
+                        // find the next older entry than the one currently shown
+                        let next = matches
+                            .iter()
+                            .find(|index| **index < self.history_index)
+                            .copied();
+                        if let Some(index) = next {
+                            self.history_index = index;
+                            self.input_text = self.history[index].command.clone();
                             self.cursor_pos = self.input_text.chars().count();
-                        } else {
+                        }
+                    }
+                    Action::HistoryNext => {
+                        reset_history_prefix = false;
+                        if let Some(prefix) = self.history_prefix.clone() {
+                            let matches = self.history_matches(&prefix);
+
+                            // find the next newer entry than the one currently shown
+                            let next = matches
+                                .iter()
+                                .rev()
+                                .find(|index| **index > self.history_index)
+                                .copied();
+                            if let Some(index) = next {
+                                self.history_index = index;
+                                self.input_text = self.history[index].command.clone();
+                            } else {
+                                // went past the newest match, so restore what was typed
+                                self.history_index = self.history.len();
+                                self.input_text = prefix;
+                                self.history_prefix = None;
+                            }
+                            self.cursor_pos = self.input_text.chars().count();
+                        } else if !self.history.is_empty() {
+                            // not going through history, so there's nothing newer than the input. clear it, like before prefix filtering
                             self.input_text = String::new();
                             self.cursor_pos = 0;
                         }
                     }
-                }
-                KeyCode::Right => {
-                    self.cursor_pos += 1;
-                    if self.cursor_pos > self.input_text.chars().count() {
-                        // if we press right arrow at the last character, fill in suggestion
-                        if let Some(suggestion) = self.get_suggestion() {
-                            self.input_text = suggestion.clone();
+                    Action::SelectForwardChar
+                    | Action::SelectBackwardChar
+                    | Action::SelectForwardWord
+                    | Action::SelectBackwardWord
+                    | Action::SelectBeginningOfLine
+                    | Action::SelectEndOfLine => {
+                        keep_selection = true;
+                        self.selection_anchor.get_or_insert(self.cursor_pos);
+                        // unlike moving without selecting, this never fills in the suggestion
+                        self.cursor_pos = match action {
+                            Action::SelectForwardChar => {
+                                (self.cursor_pos + 1).min(self.input_text.chars().count())
+                            }
+                            Action::SelectBackwardChar => self.cursor_pos.saturating_sub(1),
+                            Action::SelectForwardWord => self.next_word_boundary(),
+                            Action::SelectBackwardWord => self.previous_word_boundary(),
+                            Action::SelectBeginningOfLine => 0,
+                            _ => self.input_text.chars().count(),
+                        };
+                    }
+                    Action::Copy => {
+                        self.copy_selection();
+                    }
+                    Action::Cut => {
+                        self.copy_selection();
+                        // also keep it for yanking, which works without a clipboard
+                        if let Some(text) = self.selected_text() {
+                            self.kill_buffer = text;
                         }
-                        // move to last char
-                        self.cursor_pos = self.input_text.chars().count();
+                        self.delete_selection();
+                    }
+                    Action::Paste => {
+                        let text = copypasta::ClipboardContext::new()
+                            .ok()
+                            .and_then(|mut context| context.get_contents().ok());
+                        if let Some(text) = text {
+                            self.delete_selection();
+                            self.insert_text(&text_to_input(&text));
+                        }
+                    }
+                    Action::CopyOrCancel => {
+                        if self.selection().is_some() {
+                            self.copy_selection();
+                        } else {
+                            self.input_text = String::new();
+                            self.listening = false;
+                        }
+                    }
+                    Action::ClearScreen => {
+                        // redraw_prompt moves up to the first line of the prompt, which should end up at the top
+                        let top = self.prompt_extra_lines as u16;
+                        queue!(stdout(), Clear(ClearType::All), MoveTo(0, top))?;
+                        self.redraw_prompt()?;
+                    }
+                    Action::OpenInEditor => {
+                        self.open_in_editor()?;
+                        self.redraw_prompt()?;
+                    }
+                    Action::ViNormalMode => {
+                        // only possible in vi mode, but the action can be bound to any key
+                        if self.config.vi_mode() {
+                            self.vi_normal_mode = true;
+                            self.redraw_prompt()?;
+                        }
+                    }
+                    Action::ViInsertMode => {
+                        self.vi_normal_mode = false;
+                        self.redraw_prompt()?;
+                    }
+                    Action::ViAppend => {
+                        self.cursor_pos =
+                            (self.cursor_pos + 1).min(self.input_text.chars().count());
+                        self.vi_normal_mode = false;
+                        self.redraw_prompt()?;
                     }
                 }
-                KeyCode::Left => {
-                    self.cursor_pos = self.cursor_pos.saturating_sub(1);
-                }
-                KeyCode::Home => {
-                    self.cursor_pos = 0;
-                }
-                KeyCode::End => {
-                    self.cursor_pos = self.input_text.chars().count();
-                }
-                _ => {}
+            }
+            if !keep_selection {
+                self.selection_anchor = None;
             }
             if reset_autocomplete_cycle {
                 self.autocomplete_cycle_index = None;
                 self.last_input_before_autocomplete = None;
             }
+            // any other key than up/down means we're no longer stepping through history
+            if reset_history_prefix && self.history_prefix.is_some() {
+                self.history_prefix = None;
+                self.history_index = self.history.len();
+            }
             self.update()?;
         }
         Ok(())
     }
     /// Prints current inputted text with color highlighting
     fn print_text(&self) -> io::Result<()> {
-        let tokens = parse_text_to_tokens(&self.input_text, true);
-        for token in tokens {
-            let color = match token.ty {
-                TokenType::Keyword => self.theme.primary_color,
-                TokenType::QuotesArg => self.theme.secondary_color,
-                TokenType::RegularArg => {
-                    if token.text.starts_with("-") {
-                        self.theme.secondary_color
-                    } else {
-                        Color::White
-                    }
-                }
-                TokenType::EnvironmentVariable => self.theme.primary_color,
-                TokenType::Special => self.theme.secondary_color,
-            };
-            queue!(stdout(), SetForegroundColor(color))?;
-            print!("{}", token.text);
+        let command_exists = |keyword: &str| self.command_exists(keyword);
+        let mut text = highlight_input(
+            &self.input_text,
+            &self.theme,
+            self.config.math,
+            Some(&command_exists),
+        );
+        if let Some((start, end)) = self.selection() {
+            text = mark_selection(&text, start, end);
         }
+        print!("{}", text);
         Ok(())
     }
+    /// Whether a keyword can be run, as a builtin, math expression, script or program
+    fn command_exists(&self, keyword: &str) -> bool {
+        if COMMANDS.iter().any(|(name, _)| *name == keyword)
+            || (self.config.math && try_eval(keyword).is_ok())
+        {
+            return true;
+        }
+        // scripts are run by their runtime, so only the script itself has to exist
+        if let Some((_, extension)) = keyword.rsplit_once(".")
+            && get_script_runtime(extension).is_some()
+        {
+            return Path::new(keyword).is_file();
+        }
+        // find_binary gives back the keyword as is if it can't find it
+        binaryfinder::find_binary(keyword, &self.path_items, &self.path_extensions)
+            .is_ok_and(|path| path.is_file())
+    }
     fn update(&self) -> io::Result<()> {
         queue!(stdout(), Clear(ClearType::FromCursorDown))?;
 
@@ -1116,15 +2046,15 @@ impl Shoe {
 
         // dont show suggestion when self.listening is false, i.e. the user just pressed enter
         // so suggestions for previous entries are hidden
-        let should_show_suggestion = self.listening && self.use_suggestions;
+        let should_show_suggestion = self.listening && self.config.suggestions;
 
         if should_show_suggestion {
             let suggestion = self.get_suggestion();
             if let Some(suggestion) = suggestion {
                 // cut suggestion to only the new part
-                let cut_suggestion = &suggestion.clone()[self.input_text.len()..];
-                // make text dark grey and italic
-                queue!(stdout(), SetForegroundColor(Color::DarkGrey))?;
+                let cut_suggestion = &suggestion[self.input_text.len()..];
+                // make text the suggestion color and italic
+                queue!(stdout(), SetForegroundColor(self.theme.suggestion_color))?;
                 queue!(stdout(), SetAttribute(crossterm::style::Attribute::Italic))?;
                 // print suggestion
                 print!("{}", cut_suggestion);
@@ -1139,8 +2069,22 @@ impl Shoe {
         }
         queue!(stdout(), SetForegroundColor(Color::Reset))?;
         let (width, _) = crossterm::terminal::size()?;
-        let start_x = self.cwd_to_str()?.chars().count() + 4;
+        let start_x = self.prompt_width;
         let width = width as usize;
+
+        // show the right prompt, unless the input (or suggestion) reaches it. it's cleared along with the input on the next update
+        if let Some(right_prompt) = &self.rendered_right_prompt
+            && let Some(right_x) = width.checked_sub(right_prompt.width + 1)
+            && start_x + cursor_steps < right_x
+        {
+            queue!(stdout(), MoveToColumn(right_x as u16))?;
+            print!("{}", right_prompt.text);
+            queue!(
+                stdout(),
+                SetForegroundColor(Color::Reset),
+                MoveToColumn((start_x + cursor_steps) as u16)
+            )?;
+        }
         if cursor_steps + start_x == width {
             print!(" ");
             cursor_steps += 1;
@@ -1160,19 +2104,90 @@ impl Shoe {
 
         Ok(())
     }
-    fn get_suggestion(&self) -> Option<&String> {
-        if self.input_text.trim().is_empty() {
+    /// Iterate over history entries (newest first) that start with the prefix, along with their index
+    fn history_entries_starting_with<'a>(
+        &'a self,
+        prefix: &'a str,
+    ) -> impl Iterator<Item = (usize, &'a String)> {
+        self.history
+            .iter()
+            .enumerate()
+            .rev()
+            .map(|(index, entry)| (index, &entry.command))
+            .filter(move |(_, item)| item.starts_with(prefix))
+    }
+    /// Get indices of history entries (newest first) that start with the prefix, skipping repeated entries
+    fn history_matches(&self, prefix: &str) -> Vec<usize> {
+        let mut seen: HashSet<&str> = HashSet::new();
+        let mut matches = Vec::new();
+        for (index, item) in self.history_entries_starting_with(prefix) {
+            if seen.insert(item) {
+                matches.push(index);
+            }
+        }
+        matches
+    }
+    /// Get an inline suggestion for the current input.
+    ///
+    /// History entries starting with the input are ranked by how often they have been run in the current directory, and how recently.
+    /// If no history entry matches, tries to autocomplete the last word instead. There are no suggestions in vi normal mode.
+    fn get_suggestion(&self) -> Option<String> {
+        // motions like l and w can move past the end of the input, which would accept the suggestion
+        if self.input_text.trim().is_empty() || self.vi_normal_mode {
             return None;
         }
-        for index in 0..self.history.len() {
-            // get items from history in reversed order
-            let item = &self.history[self.history.len() - 1 - index];
+        let cwd = std::env::current_dir()
+            .map(|path| absolute_pathbuf_to_string(&path))
+            .unwrap_or_default();
+
+        // score of each command, and index of its most recent use
+        let mut scores: HashMap<&String, (f64, usize)> = HashMap::new();
+        for (index, item) in self.history_entries_starting_with(&self.input_text) {
+            // newer entries weigh more, and entries from the current directory weigh a lot more
+            let recency = (index + 1) as f64 / self.history.len() as f64;
+            let directory_weight = if self.history[index].cwd == cwd {
+                4.0
+            } else {
+                1.0
+            };
+            let score = scores.entry(item).or_insert((0.0, index));
+            score.0 += recency * directory_weight;
+        }
+        let best = scores
+            .into_iter()
+            .max_by(|(_, a), (_, b)| a.0.total_cmp(&b.0).then(a.1.cmp(&b.1)))
+            .map(|(item, _)| item.clone());
+        if best.is_some() {
+            return best;
+        }
 
-            if item.starts_with(&self.input_text) {
-                return Some(item);
+        self.get_completion_suggestion()
+    }
+    /// Get a suggestion by autocompleting the last word of the input, i.e. to an existing path
+    fn get_completion_suggestion(&self) -> Option<String> {
+        let tokens = parse_text_to_tokens(&self.input_text, true, self.config.math);
+        let last = tokens.back()?;
+        if last.text.is_empty() || last.text.ends_with(' ') {
+            return None;
+        }
+        let completion = match last.ty {
+            TokenType::Keyword => autocomplete_keyword(
+                &last.text,
+                0,
+                &self.path_executables,
+                self.config.completion.ignore_case,
+            ),
+            TokenType::RegularArg => {
+                autocomplete_path(&last.text, 0, self.config.completion.ignore_case)
             }
+            _ => None,
+        }?;
+        // only suggest completions that the input can be extended to
+        let new_part = completion.strip_prefix(&last.text)?;
+        if new_part.is_empty() || new_part.contains(' ') {
+            return None;
         }
-        None
+        Some(self.input_text.clone() + new_part)
     }
     fn tokens_to_commands_vec(tokens: &VecDeque<Token>) -> io::Result<Vec<Command<'_>>> {
         let mut commands: Vec<Command> = Vec::new();
@@ -1186,6 +2201,11 @@ impl Shoe {
             let mut done = false;
             if let Some(command) = &mut current_command {
                 if let TokenType::Special = token.ty {
+                    let is_redirect = matches!(token.text.as_str(), ">" | ">>" | "<");
+                    if is_redirect && index >= tokens.len() {
+                        let message = format!("no target for '{}'", token.text);
+                        return Err(std::io::Error::other(message));
+                    }
                     match token.text.as_str() {
                         ";" | "&" => {
                             done = true;
@@ -1203,10 +2223,6 @@ impl Shoe {
                             command.output_modifier = CommandOutputModifier::Piped;
                         }
                         ">" => {
-                            if index >= tokens.len() {
-                                let message = format!("no target for '{}'", token.text);
-                                return Err(std::io::Error::other(message));
-                            }
                             command.output_modifier =
                                 CommandOutputModifier::WriteTo(tokens[index].text.clone(), false);
                             index += 1;
@@ -1252,40 +2268,107 @@ impl Shoe {
         }
         Ok(commands)
     }
-    fn execute_command_string(&mut self, command: &String, allow_history: bool) -> io::Result<()> {
+    /// Run a command line, timing it. Interactive command lines (typed at the prompt, not from the rc file or -c) are stored in history,
+    /// and how long they took is shown if they ran for long
+    fn execute_command_string(&mut self, command: &String, interactive: bool) -> io::Result<()> {
         if command.trim().is_empty() {
             self.history_index = self.history.len();
             return Ok(());
         }
+        self.history_prefix = None;
+        self.interactive = interactive;
+
+        // store where and when the command was run, for the history entry
+        let cwd = absolute_pathbuf_to_string(&std::env::current_dir()?);
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |time| time.as_secs());
+        let start = Instant::now();
+
+        self.run_command_string(command, false)?;
+        let duration = start.elapsed().as_millis() as u64;
+        self.last_duration = Some(duration);
+        self.enviroment_variables
+            .insert(String::from("CMD_DURATION"), duration.to_string());
+        if interactive
+            && let Some(threshold) = self.duration_threshold()
+            && duration >= threshold
+        {
+            queue!(stdout(), SetForegroundColor(Color::DarkGrey))?;
+            println!("took {}", format_duration(duration));
+            queue!(stdout(), SetForegroundColor(Color::Reset))?;
+        }
+
         let mut should_store_history = true;
-        if let Some(last_input) = self.history.last() {
-            if last_input == command {
-                should_store_history = false;
-            }
+        if let Some(last_input) = self.history.last()
+            && last_input.command == *command
+        {
+            should_store_history = false;
         }
-        should_store_history &= allow_history;
+        should_store_history &= interactive;
+        // like in other shells, a leading space means the command shouldn't be remembered
+        should_store_history &= !command.starts_with(' ');
+        should_store_history &= !self
+            .history_ignore
+            .iter()
+            .chain(&self.config_history_ignore)
+            .any(|pattern| pattern.matches(command));
 
         if should_store_history {
-            self.history.push(command.clone());
-
-            if let Some(history_path) = &self.history_path {
-                std::fs::write(history_path, self.history.join("\n"))?;
+            let entry = HistoryEntry {
+                command: command.clone(),
+                timestamp,
+                cwd,
+                duration,
+                exit_code: self.last_exit_code,
+            };
+            if let Some(history_file) = &mut self.history_file {
+                let new_entries = history_file.append(&entry);
+                self.merge_history_result(new_entries)?;
             }
+            self.history.push(entry);
         }
 
         self.history_index = self.history.len();
-
-        let mut tokens = filter_tokens_and_parse_vars(parse_text_to_tokens(command, false));
+        Ok(())
+    }
+    /// How long (in milliseconds) a command has to run for its duration to be shown. None if durations shouldn't be shown.
+    ///
+    /// Set in seconds with the duration_threshold setting, or to `off`. The SHOE_DURATION_THRESHOLD variable overrides the setting.
+    fn duration_threshold(&self) -> Option<u64> {
+        let value = self
+            .enviroment_variables
+            .get("SHOE_DURATION_THRESHOLD")
+            .cloned()
+            .or_else(|| env::var("SHOE_DURATION_THRESHOLD").ok());
+        if let Some(threshold) = value.as_deref().and_then(config::parse_duration_threshold) {
+            return threshold;
+        }
+        // the config was checked when loaded
+        self.config.duration_threshold().ok().flatten()
+    }
+    /// Parse and run a command string. If capture_output is set, output that would be shown is returned instead
+    fn run_command_string(&mut self, command: &str, capture_output: bool) -> io::Result<Vec<u8>> {
+        let mut tokens = filter_tokens_and_parse_vars(
+            parse_text_to_tokens(command, false, self.config.math),
+            &self.enviroment_variables,
+        );
 
         // check if input may be math expression, if so, evaluate it
         let eval_result = try_eval(command);
-        if let Ok(eval) = eval_result {
+        if self.config.math
+            && let Ok(eval) = eval_result
+        {
+            self.last_exit_code = 0;
+            if capture_output {
+                return Ok(format!("{}\n", eval).into_bytes());
+            }
             queue!(stdout(), SetForegroundColor(Color::Reset))?;
             println!("{}", eval);
-            return Ok(());
+            return Ok(Vec::new());
         }
 
-        if self.substitute_tildes {
+        if self.config.substitute_tildes {
             for token in tokens.iter_mut() {
                 if token.text.contains('~') {
                     let new = shellexpand::tilde(&token.text).to_string();
@@ -1296,15 +2379,22 @@ impl Shoe {
 
         // store any errors that arise here
         let mut err: Option<std::io::Error> = None;
+        let mut output = Vec::new();
 
         let commands = Self::tokens_to_commands_vec(&tokens);
         match commands {
-            Ok(commands) => {
-                let execution_result = self.execute_commands(commands);
-
-                if let Err(error) = execution_result {
+            Ok(mut commands) => {
+                if capture_output {
+                    for command in commands.iter_mut() {
+                        if let CommandOutputModifier::Default = command.output_modifier {
+                            command.output_modifier = CommandOutputModifier::Captured;
+                        }
+                    }
+                }
+                match self.execute_commands(commands) {
+                    Ok(captured_output) => output = captured_output,
                     // if command execution failed, store error in err
-                    err = Some(error);
+                    Err(error) => err = Some(error),
                 }
             }
             Err(error) => {
@@ -1314,12 +2404,13 @@ impl Shoe {
         }
 
         if let Some(err) = err {
+            self.last_exit_code = 1;
             queue!(stdout(), SetForegroundColor(self.theme.err_color))?;
             println!("{}", err);
         }
 
         queue!(stdout(), SetForegroundColor(Color::Reset))?;
-        Ok(())
+        Ok(output)
     }
     fn start(&mut self, rc: Vec<String>) -> io::Result<()> {
         // set window title
@@ -1364,28 +2455,48 @@ impl Shoe {
     }
     fn listen(&mut self) -> io::Result<String> {
         self.listening = true;
+        // every command starts in insert mode, like in other shells
+        self.vi_normal_mode = false;
+        self.vi_pending = None;
 
-        queue!(stdout(), SetForegroundColor(self.theme.primary_color))?;
-        print!("[");
-        queue!(stdout(), SetForegroundColor(Color::White))?;
-        print!("{}", self.cwd_to_str()?);
-        queue!(stdout(), SetForegroundColor(self.theme.primary_color))?;
-        print!("]> ");
+        // pick up commands that other sessions have run since last time
+        if self.share_history
+            && let Some(history_file) = &mut self.history_file
+        {
+            let new_entries = history_file.read_new();
+            self.merge_history_result(new_entries)?;
+            self.history_index = self.history.len();
+        }
+
+        let prompt = self.render_prompt(PromptKind::Main)?.unwrap();
+        self.prompt_width = prompt.width;
+        self.prompt_extra_lines = prompt.extra_lines;
+        print!("{}", prompt.text);
+        self.rendered_right_prompt = self.render_prompt(PromptKind::Right)?;
+        // draw the right prompt
+        self.update()?;
 
         stdout().flush()?;
         while self.listening {
             let mut e = event::read()?;
             if let Event::Key(key) = &mut e
                 && key.is_press()
-                && key.code.is_char('w')
-                && key.modifiers.contains(KeyModifiers::CONTROL)
             {
-                key.code = KeyCode::Backspace;
+                self.keymap.apply(key);
             }
 
             self.handle_key_press(e)?;
         }
-        if self.input_text.chars().count() != 0 {
+        if let Some(transient) = self.render_prompt(PromptKind::Transient)? {
+            // replace the prompt with the transient prompt. the cursor is at the start of the input
+            if self.prompt_extra_lines > 0 {
+                queue!(stdout(), MoveUp(self.prompt_extra_lines as u16))?;
+            }
+            queue!(stdout(), MoveToColumn(0), Clear(ClearType::FromCursorDown))?;
+            print!("{}", transient.text);
+            self.print_text()?;
+            queue!(stdout(), SetForegroundColor(Color::Reset))?;
+        } else if self.input_text.chars().count() != 0 {
             queue!(stdout(), MoveRight(self.input_text.chars().count() as u16))?;
         }
         stdout().lock().write_all(b"\n")?;
@@ -1420,6 +2531,7 @@ impl std::fmt::Debug for Token {
 fn main() {
     let args = std::env::args();
     let mut use_history = true;
+    let mut share_history = false;
     let mut use_rc = true;
 
     // will be Some if the -c or -k argument has been hit, if so, all following args are appended to this
@@ -1434,6 +2546,9 @@ fn main() {
                 "--no-history" => {
                     use_history = false;
                 }
+                "--share-history" => {
+                    share_history = true;
+                }
                 "--no-rc" => {
                     use_rc = false;
                 }
@@ -1460,11 +2575,7 @@ fn main() {
     }
 
     let path: Option<String> = if use_history {
-        let history_path = shellexpand::tilde("~/.shoehistory").to_string();
-        if std::fs::metadata(&history_path).is_err() {
-            std::fs::write(&history_path, "").expect("Couldn't create ~/.shoehistory");
-        }
-        Some(history_path)
+        Some(shellexpand::tilde("~/.shoehistory").to_string())
     } else {
         None
     };
@@ -1488,8 +2599,30 @@ fn main() {
         rc.push(run_command);
     }
 
+    let config = match Config::load() {
+        Ok((config, errors)) => {
+            // invalid settings are left at their default, so the rest of the config still applies
+            if !errors.is_empty() {
+                queue!(stdout(), SetForegroundColor(utils::DEFAULT_ERR_COLOR)).unwrap();
+                for error in errors {
+                    println!("{}", error);
+                }
+                println!("Using the default for those settings");
+                queue!(stdout(), SetForegroundColor(Color::Reset)).unwrap();
+            }
+            config
+        }
+        Err(error) => {
+            queue!(stdout(), SetForegroundColor(utils::DEFAULT_ERR_COLOR)).unwrap();
+            println!("{}", error);
+            println!("Using the default settings");
+            queue!(stdout(), SetForegroundColor(Color::Reset)).unwrap();
+            Config::default()
+        }
+    };
+
     // construct shoe instance
-    let mut shoe = Shoe::new(path);
+    let mut shoe = Shoe::new(path, share_history, config);
 
     // if argument was -c, execute the commands immediately and then return
     if exit_after_run_command {
@@ -1503,3 +2636,62 @@ fn main() {
     // run, and pass rc commands
     shoe.start(rc).unwrap();
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn commands_of(text: &str) -> io::Result<Vec<(String, Vec<String>)>> {
+        let tokens = parse_text_to_tokens(text, false, false);
+        let commands = Shoe::tokens_to_commands_vec(&tokens)?;
+        Ok(commands
+            .iter()
+            .map(|command| {
+                let args = command.args.iter().map(|arg| arg.to_string()).collect();
+                (command.keyword.clone(), args)
+            })
+            .collect())
+    }
+
+    #[test]
+    fn parses_redirects() {
+        let tokens = parse_text_to_tokens("cat < in.txt >> out.txt", false, false);
+        let commands = Shoe::tokens_to_commands_vec(&tokens).unwrap();
+        assert_eq!(commands.len(), 1);
+        assert!(matches!(
+            &commands[0].input_modifier,
+            CommandInputModifier::ReadFrom(path) if path == "in.txt"
+        ));
+        assert!(matches!(
+            &commands[0].output_modifier,
+            CommandOutputModifier::WriteTo(path, true) if path == "out.txt"
+        ));
+        assert!(commands[0].args.is_empty());
+
+        assert_eq!(
+            commands_of("echo hi > out.txt; ls").unwrap(),
+            [
+                (String::from("echo"), vec![String::from("hi")]),
+                (String::from("ls"), vec![])
+            ]
+        );
+    }
+
+    #[test]
+    fn turns_text_into_input() {
+        // the line break editors end files with doesn't make it multi-line
+        assert_eq!(text_to_input("echo  hi \n"), "echo  hi ");
+        assert_eq!(text_to_input("echo hi\r\n"), "echo hi");
+        assert_eq!(text_to_input("echo hi"), "echo hi");
+        assert_eq!(text_to_input("cd src\n\n  ls \n"), "cd src; ls");
+        assert_eq!(text_to_input("echo hi\n\n"), "echo hi");
+    }
+
+    #[test]
+    fn redirects_without_target_fail() {
+        for text in ["echo hi >", "echo hi >>", "cat <"] {
+            let error = commands_of(text).unwrap_err();
+            assert!(error.to_string().starts_with("no target for"), "{}", text);
+        }
+    }
+}
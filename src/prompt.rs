@@ -0,0 +1,249 @@
+//! Prompt templates, set with the `prompt` builtin (usually from ~/.shoerc)
+//!
+//! Besides the main prompt, there can be a right prompt, shown at the right edge of the input line,
+//! and a transient prompt, which replaces the main prompt of commands that have been run, to keep scrollback short.
+//!
+//! A template is text with placeholders and color tags in braces, like `{prompt}[{white}{cwd}{prompt}]> `.
+//! Use `{{` and `}}` for literal braces.
+
+use std::io;
+
+use crossterm::style::{Color, SetForegroundColor};
+use unicode_width::UnicodeWidthStr;
+
+use crate::{theme::Theme, utils::parse_color};
+
+/// The prompt used when none is set, `[cwd]> `, with the vi mode in front in vi mode
+pub const DEFAULT_PROMPT: &str = "{prompt}{vi_mode}[{white}{cwd}{prompt}]> ";
+
+/// Values that can be shown in the prompt
+#[derive(Clone, Copy, PartialEq)]
+pub enum Placeholder {
+    /// The cwd, with the home directory as ~
+    Cwd,
+    /// Only the last part of the cwd
+    ShortCwd,
+    User,
+    Host,
+    /// Local time, as hours:minutes:seconds
+    Time,
+    /// Exit code of the last command
+    Status,
+    /// How long the last command took
+    Duration,
+    /// Git status: the checked out branch, how far ahead/behind its upstream it is, and whether there are staged (`+`) or unstaged (`*`) changes
+    Git,
+    /// Only the checked out git branch
+    Branch,
+    /// Amount of background jobs
+    Jobs,
+    /// The vi mode, `[N] ` in normal mode and `[I] ` in insert mode. Nothing when vi mode is off
+    ViMode,
+}
+
+impl Placeholder {
+    const NAMES: &[(&str, Placeholder)] = &[
+        ("cwd", Placeholder::Cwd),
+        ("short_cwd", Placeholder::ShortCwd),
+        ("user", Placeholder::User),
+        ("host", Placeholder::Host),
+        ("time", Placeholder::Time),
+        ("status", Placeholder::Status),
+        ("duration", Placeholder::Duration),
+        ("git", Placeholder::Git),
+        ("branch", Placeholder::Branch),
+        ("jobs", Placeholder::Jobs),
+        ("vi_mode", Placeholder::ViMode),
+    ];
+}
+
+/// Which prompt a template is for
+#[derive(Clone, Copy)]
+pub enum PromptKind {
+    Main,
+    /// Shown at the right edge of the input line, until the input reaches it
+    Right,
+    /// Replaces the main prompt once enter is pressed
+    Transient,
+}
+
+/// A color tag, which colors the text after it
+#[derive(Clone, Copy)]
+enum PromptColor {
+    /// The prompt color of the theme
+    Prompt,
+    Primary,
+    Secondary,
+    Error,
+    /// Any other color, by name (like `red`) or hex (like `#ff8800`)
+    Fixed(Color),
+}
+
+enum Segment {
+    Text(String),
+    Color(PromptColor),
+    Placeholder(Placeholder),
+    Newline,
+}
+
+pub struct PromptTemplate {
+    segments: Vec<Segment>,
+}
+
+/// A rendered prompt, ready to be printed
+pub struct RenderedPrompt {
+    pub text: String,
+    /// Width in columns of the last line of the prompt, which is where input starts
+    pub width: usize,
+    /// Amount of lines before the last line
+    pub extra_lines: usize,
+}
+
+impl PromptTemplate {
+    pub fn parse(template: &str) -> io::Result<Self> {
+        let mut segments = Vec::new();
+        let mut text = String::new();
+        let mut chars = template.chars().peekable();
+        while let Some(char) = chars.next() {
+            match char {
+                '{' if chars.peek() == Some(&'{') => {
+                    chars.next();
+                    text.push('{');
+                }
+                '}' if chars.peek() == Some(&'}') => {
+                    chars.next();
+                    text.push('}');
+                }
+                '{' => {
+                    let mut name = String::new();
+                    loop {
+                        match chars.next() {
+                            Some('}') => break,
+                            Some(char) => name.push(char),
+                            None => {
+                                let message = format!("Unclosed '{{' in prompt: {{{}", name);
+                                return Err(io::Error::other(message));
+                            }
+                        }
+                    }
+                    if !text.is_empty() {
+                        segments.push(Segment::Text(std::mem::take(&mut text)));
+                    }
+                    segments.push(Self::parse_tag(&name)?);
+                }
+                _ => text.push(char),
+            }
+        }
+        if !text.is_empty() {
+            segments.push(Segment::Text(text));
+        }
+        Ok(PromptTemplate { segments })
+    }
+
+    /// Parse a template for a kind of prompt, checking that the kind can show it
+    pub fn parse_kind(template: &str, kind: PromptKind) -> io::Result<Self> {
+        let template = Self::parse(template)?;
+        if matches!(kind, PromptKind::Right) && !template.is_single_line() {
+            return Err(io::Error::other(
+                "The right prompt can't have more than one line",
+            ));
+        }
+        Ok(template)
+    }
+
+    fn parse_tag(name: &str) -> io::Result<Segment> {
+        if let Some((_, placeholder)) = Placeholder::NAMES.iter().find(|(key, _)| *key == name) {
+            return Ok(Segment::Placeholder(*placeholder));
+        }
+        let segment = match name {
+            "newline" => Segment::Newline,
+            "prompt" => Segment::Color(PromptColor::Prompt),
+            "primary" => Segment::Color(PromptColor::Primary),
+            "secondary" => Segment::Color(PromptColor::Secondary),
+            "error" => Segment::Color(PromptColor::Error),
+            _ => match parse_color(name) {
+                Some(color) => Segment::Color(PromptColor::Fixed(color)),
+                None => {
+                    let message = format!("Unknown placeholder or color in prompt: {{{}}}", name);
+                    return Err(io::Error::other(message));
+                }
+            },
+        };
+        Ok(segment)
+    }
+
+    fn is_single_line(&self) -> bool {
+        !self
+            .segments
+            .iter()
+            .any(|segment| matches!(segment, Segment::Newline))
+    }
+
+    /// Whether the template shows any of the placeholders
+    pub fn uses(&self, placeholders: &[Placeholder]) -> bool {
+        self.segments.iter().any(|segment| {
+            matches!(segment, Segment::Placeholder(placeholder) if placeholders.contains(placeholder))
+        })
+    }
+
+    /// Render the template, getting the values of placeholders from `value_of`
+    pub fn render(
+        &self,
+        theme: &Theme,
+        mut value_of: impl FnMut(Placeholder) -> String,
+    ) -> RenderedPrompt {
+        let mut text = String::new();
+        let mut width = 0;
+        let mut extra_lines = 0;
+        // the color set by the last color tag, to go back to after git info
+        let mut current_color = Color::Reset;
+        for segment in &self.segments {
+            match segment {
+                Segment::Text(segment_text) => {
+                    text += segment_text;
+                    width += segment_text.width();
+                }
+                Segment::Placeholder(placeholder) => {
+                    let value = value_of(*placeholder);
+                    width += value.width();
+                    let is_git = matches!(placeholder, Placeholder::Git | Placeholder::Branch);
+                    if is_git && !value.is_empty() {
+                        text += &SetForegroundColor(theme.secondary_color).to_string();
+                        text += &value;
+                        text += &SetForegroundColor(current_color).to_string();
+                    } else {
+                        text += &value;
+                    }
+                }
+                Segment::Color(color) => {
+                    let color = match color {
+                        PromptColor::Prompt => theme.prompt_color,
+                        PromptColor::Primary => theme.primary_color,
+                        PromptColor::Secondary => theme.secondary_color,
+                        PromptColor::Error => theme.err_color,
+                        PromptColor::Fixed(color) => *color,
+                    };
+                    current_color = color;
+                    text += &SetForegroundColor(color).to_string();
+                }
+                Segment::Newline => {
+                    // the prompt is printed in raw mode, so the carriage return is needed
+                    text += "\r\n";
+                    width = 0;
+                    extra_lines += 1;
+                }
+            }
+        }
+        RenderedPrompt {
+            text,
+            width,
+            extra_lines,
+        }
+    }
+}
+
+impl Default for PromptTemplate {
+    fn default() -> Self {
+        PromptTemplate::parse(DEFAULT_PROMPT).unwrap()
+    }
+}
@@ -0,0 +1,277 @@
+//! Structured output for builtin commands
+//!
+//! Builtins that list things (like `ls` or `history`) can output [Records] instead of plain text.
+//! They are shown as an aligned table, which is written without colors when piped or redirected, so it still works with tools like grep.
+//! Scripts can ask for JSON (or CSV) instead, so they can consume the output without having to parse text.
+
+use std::{
+    collections::VecDeque,
+    io::{Result, Write},
+};
+
+use crossterm::{
+    queue,
+    style::{Color, SetForegroundColor},
+};
+use unicode_width::UnicodeWidthStr;
+
+use crate::{
+    commands::CommandContext,
+    utils::{format_duration, format_size, format_timestamp},
+};
+
+/// How records should be written
+#[derive(Clone, Copy, PartialEq)]
+pub enum OutputFormat {
+    Table,
+    Json,
+    Csv,
+}
+
+/// Get the output format to use for a command, and its args with the format flags (`--json` and `--csv`) removed.
+///
+/// Without flags, records are shown as a table, even when piped or redirected.
+pub fn take_format_flags<'a>(context: &CommandContext<'a>) -> (OutputFormat, VecDeque<&'a str>) {
+    let mut format = OutputFormat::Table;
+    let mut args = VecDeque::new();
+    for arg in context.args.iter() {
+        match *arg {
+            "--json" => format = OutputFormat::Json,
+            "--csv" => format = OutputFormat::Csv,
+            _ => args.push_back(*arg),
+        }
+    }
+    (format, args)
+}
+
+/// The value of a field, which decides how it is shown in a table, and how it is written as JSON/CSV
+pub enum Value {
+    Text(String),
+    Integer(i64),
+    /// Size in bytes, shown human readable in tables
+    Size(u64),
+    /// Unix timestamp in seconds, shown as local time in tables
+    Time(i64),
+    /// Duration in milliseconds, shown human readable in tables
+    Duration(u64),
+    /// Missing value, `null` in JSON
+    Empty,
+}
+
+pub struct Field {
+    value: Value,
+    /// Color of the field when shown in a table
+    color: Option<Color>,
+}
+
+impl Field {
+    pub fn text(text: impl Into<String>) -> Self {
+        Field::from(Value::Text(text.into()))
+    }
+    pub fn integer(value: i64) -> Self {
+        Field::from(Value::Integer(value))
+    }
+    pub fn size(bytes: u64) -> Self {
+        Field::from(Value::Size(bytes))
+    }
+    pub fn time(timestamp: i64) -> Self {
+        Field::from(Value::Time(timestamp))
+    }
+    pub fn duration(millis: u64) -> Self {
+        Field::from(Value::Duration(millis))
+    }
+    pub fn empty() -> Self {
+        Field::from(Value::Empty)
+    }
+    pub fn colored(mut self, color: Color) -> Self {
+        self.color = Some(color);
+        self
+    }
+    /// Text shown in tables
+    fn display(&self) -> String {
+        match &self.value {
+            // keep multi-line text on one line
+            Value::Text(text) => text.replace('\n', " "),
+            Value::Integer(value) => value.to_string(),
+            Value::Size(bytes) => format_size(*bytes),
+            Value::Time(timestamp) => format_timestamp(*timestamp),
+            Value::Duration(millis) => format_duration(*millis),
+            Value::Empty => String::new(),
+        }
+    }
+    /// Whether the field should be right aligned in tables
+    fn is_number(&self) -> bool {
+        matches!(
+            self.value,
+            Value::Integer(_) | Value::Size(_) | Value::Duration(_)
+        )
+    }
+    fn to_json(&self) -> String {
+        match &self.value {
+            Value::Text(text) => json_string(text),
+            Value::Integer(value) => value.to_string(),
+            Value::Size(value) | Value::Duration(value) => value.to_string(),
+            Value::Time(value) => value.to_string(),
+            Value::Empty => String::from("null"),
+        }
+    }
+    fn to_csv(&self) -> String {
+        let text = match &self.value {
+            Value::Text(text) => text.clone(),
+            Value::Integer(value) => value.to_string(),
+            Value::Size(value) | Value::Duration(value) => value.to_string(),
+            Value::Time(value) => value.to_string(),
+            Value::Empty => String::new(),
+        };
+        if text.contains([',', '"', '\n', '\r']) {
+            format!("\"{}\"", text.replace('"', "\"\""))
+        } else {
+            text
+        }
+    }
+}
+
+impl From<Value> for Field {
+    fn from(value: Value) -> Self {
+        Field { value, color: None }
+    }
+}
+
+/// Rows of named fields
+pub struct Records {
+    columns: Vec<&'static str>,
+    rows: Vec<Vec<Field>>,
+}
+
+impl Records {
+    pub fn new(columns: &[&'static str]) -> Self {
+        Records {
+            columns: columns.to_vec(),
+            rows: Vec::new(),
+        }
+    }
+    /// Add a row. Should have one field per column
+    pub fn push(&mut self, row: Vec<Field>) {
+        debug_assert_eq!(row.len(), self.columns.len());
+        self.rows.push(row);
+    }
+    /// Write the records to the command's stdout, in the given format
+    pub fn write(&self, context: &mut CommandContext, format: OutputFormat) -> Result<()> {
+        match format {
+            OutputFormat::Table => self.write_table(context),
+            OutputFormat::Json => self.write_json(context.stdout),
+            OutputFormat::Csv => self.write_csv(context.stdout),
+        }
+    }
+    fn write_table(&self, context: &mut CommandContext) -> Result<()> {
+        let cells: Vec<Vec<String>> = self
+            .rows
+            .iter()
+            .map(|row| row.iter().map(Field::display).collect())
+            .collect();
+
+        // width of each column is the width of its widest cell or header, in terminal columns
+        let mut widths: Vec<usize> = self.columns.iter().map(|name| name.width()).collect();
+        for row in &cells {
+            for (index, cell) in row.iter().enumerate() {
+                widths[index] = widths[index].max(cell.width());
+            }
+        }
+        let last = self.columns.len() - 1;
+        // colors would end up in files and other programs' input
+        let colored = context.output_is_terminal;
+
+        if colored {
+            queue!(
+                context.stdout,
+                SetForegroundColor(context.theme.primary_color)
+            )?;
+        }
+        for (index, name) in self.columns.iter().enumerate() {
+            if index == last {
+                writeln!(context.stdout, "{}", name)?;
+            } else {
+                write!(context.stdout, "{}{}  ", name, padding(name, widths[index]))?;
+            }
+        }
+        for (row, row_cells) in self.rows.iter().zip(&cells) {
+            for (index, (field, cell)) in row.iter().zip(row_cells).enumerate() {
+                if colored {
+                    queue!(
+                        context.stdout,
+                        SetForegroundColor(field.color.unwrap_or(Color::Reset))
+                    )?;
+                }
+                let padding = padding(cell, widths[index]);
+                if field.is_number() {
+                    write!(context.stdout, "{}{}", padding, cell)?;
+                } else if index == last {
+                    // dont pad the last column, to avoid trailing whitespace
+                    write!(context.stdout, "{}", cell)?;
+                } else {
+                    write!(context.stdout, "{}{}", cell, padding)?;
+                }
+                if index != last {
+                    write!(context.stdout, "  ")?;
+                }
+            }
+            writeln!(context.stdout)?;
+        }
+        if colored {
+            queue!(context.stdout, SetForegroundColor(Color::Reset))?;
+        }
+        Ok(())
+    }
+    /// Write as a JSON array, with one object per line
+    fn write_json(&self, stdout: &mut Vec<u8>) -> Result<()> {
+        writeln!(stdout, "[")?;
+        for (row_index, row) in self.rows.iter().enumerate() {
+            let fields: Vec<String> = self
+                .columns
+                .iter()
+                .zip(row)
+                .map(|(name, field)| format!("{}:{}", json_string(name), field.to_json()))
+                .collect();
+            let comma = if row_index + 1 == self.rows.len() {
+                ""
+            } else {
+                ","
+            };
+            writeln!(stdout, "{{{}}}{}", fields.join(","), comma)?;
+        }
+        writeln!(stdout, "]")?;
+        Ok(())
+    }
+    fn write_csv(&self, stdout: &mut Vec<u8>) -> Result<()> {
+        writeln!(stdout, "{}", self.columns.join(","))?;
+        for row in &self.rows {
+            let fields: Vec<String> = row.iter().map(Field::to_csv).collect();
+            writeln!(stdout, "{}", fields.join(","))?;
+        }
+        Ok(())
+    }
+}
+
+/// Quote and escape a string for JSON
+fn json_string(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len() + 2);
+    escaped.push('"');
+    for char in text.chars() {
+        match char {
+            '"' => escaped += "\\\"",
+            '\\' => escaped += "\\\\",
+            '\n' => escaped += "\\n",
+            '\r' => escaped += "\\r",
+            '\t' => escaped += "\\t",
+            c if (c as u32) < 0x20 => escaped += &format!("\\u{:04x}", c as u32),
+            c => escaped.push(c),
+        }
+    }
+    escaped.push('"');
+    escaped
+}
+
+/// Spaces to pad text to a width. Formatting with a width counts chars, which doesn't work for wide characters like emoji
+fn padding(text: &str, width: usize) -> String {
+    " ".repeat(width.saturating_sub(text.width()))
+}
@@ -0,0 +1,155 @@
+//! Color themes, chosen with the `theme` builtin
+//!
+//! Besides the built-in themes, users can add their own as TOML files in ~/.config/shoe/themes/, like:
+//!
+//! ```toml
+//! name = "ocean"
+//! primary = "#00aaff"
+//! secondary = "#ffaa00"
+//! path = "cyan"
+//! ```
+//!
+//! Only `primary` and `secondary` are required. The name defaults to the file name.
+//! The chosen theme is remembered in the config file, so it persists across sessions (except in debug builds, which start with the debug theme).
+
+use std::{borrow::Cow, fs, io};
+
+use crossterm::style::Color;
+use serde::Deserialize;
+
+use crate::utils::{DEFAULT_ERR_COLOR, config_dir, hex_to_color, parse_color};
+
+#[derive(Clone)]
+pub struct Theme {
+    pub name: Cow<'static, str>,
+    /// Color of keywords, and the main accent color
+    pub primary_color: Color,
+    pub secondary_color: Color,
+    pub err_color: Color,
+    /// Color of inline suggestions
+    pub suggestion_color: Color,
+    /// Color of regular arguments
+    pub argument_color: Color,
+    /// Color of arguments starting with `-`
+    pub flag_color: Color,
+    /// Color of arguments that look like paths
+    pub path_color: Color,
+    /// Color of the `{prompt}` tag, used by the default prompt
+    pub prompt_color: Color,
+}
+
+impl Theme {
+    /// A theme with only the primary and secondary colors picked, and defaults for the rest
+    const fn builtin(name: &'static str, primary: u32, secondary: u32) -> Self {
+        Theme {
+            name: Cow::Borrowed(name),
+            primary_color: hex_to_color(primary),
+            secondary_color: hex_to_color(secondary),
+            err_color: DEFAULT_ERR_COLOR,
+            suggestion_color: Color::DarkGrey,
+            argument_color: Color::White,
+            flag_color: hex_to_color(secondary),
+            path_color: Color::White,
+            prompt_color: hex_to_color(primary),
+        }
+    }
+}
+
+pub static THEMES: &[Theme] = &[
+    Theme::builtin("gold", 0xFFC145, 0x5B5F97),
+    Theme::builtin("earth", 0x45FF8C, 0x97645B),
+    Theme::builtin("element", 0xFF4C4F, 0x89B4E5),
+    Theme::builtin("lime", 0x9DE64E, 0x72A6FF),
+    Theme::builtin("fire", 0xFF2B32, 0xFF6E00),
+];
+
+/// The default theme of debug builds
+pub static DEBUG_THEME: Theme = Theme::builtin("debug", 0xb2deff, 0xffd68f);
+
+/// The built-in themes. Debug builds also have the debug theme, so it can be switched back to
+pub fn builtin_themes() -> Vec<Theme> {
+    let mut themes = THEMES.to_vec();
+    if cfg!(debug_assertions) {
+        themes.push(DEBUG_THEME.clone());
+    }
+    themes
+}
+
+/// Contents of a theme file. Colors are names or hex codes, like in prompt color tags
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ThemeFile {
+    name: Option<String>,
+    primary: String,
+    secondary: String,
+    error: Option<String>,
+    suggestion: Option<String>,
+    argument: Option<String>,
+    flag: Option<String>,
+    path: Option<String>,
+    prompt: Option<String>,
+}
+
+/// Parse a theme file. Colors that aren't set default to the same as in the built-in themes
+fn parse_theme(file_name: &str, text: &str) -> io::Result<Theme> {
+    let file: ThemeFile = toml::from_str(text).map_err(|error| {
+        io::Error::other(format!("Invalid theme {}: {}", file_name, error.message()))
+    })?;
+    let color = |role: &str, value: Option<&String>, default: Color| match value {
+        Some(value) => parse_color(value).ok_or_else(|| {
+            let message = format!("Invalid {} color '{}' in theme {}", role, value, file_name);
+            io::Error::other(message)
+        }),
+        None => Ok(default),
+    };
+    let primary_color = color("primary", Some(&file.primary), Color::Reset)?;
+    let secondary_color = color("secondary", Some(&file.secondary), Color::Reset)?;
+    let name = file.name.unwrap_or_else(|| {
+        file_name
+            .strip_suffix(".toml")
+            .unwrap_or(file_name)
+            .to_string()
+    });
+    Ok(Theme {
+        name: Cow::Owned(name),
+        primary_color,
+        secondary_color,
+        err_color: color("error", file.error.as_ref(), DEFAULT_ERR_COLOR)?,
+        suggestion_color: color("suggestion", file.suggestion.as_ref(), Color::DarkGrey)?,
+        argument_color: color("argument", file.argument.as_ref(), Color::White)?,
+        flag_color: color("flag", file.flag.as_ref(), secondary_color)?,
+        path_color: color("path", file.path.as_ref(), Color::White)?,
+        prompt_color: color("prompt", file.prompt.as_ref(), primary_color)?,
+    })
+}
+
+/// Load the themes in ~/.config/shoe/themes/, sorted by name. Also returns errors of files that couldn't be loaded
+pub fn load_user_themes() -> (Vec<Theme>, Vec<io::Error>) {
+    let mut themes = Vec::new();
+    let mut errors = Vec::new();
+    let Ok(entries) = fs::read_dir(config_dir().join("themes")) else {
+        return (themes, errors);
+    };
+    for entry in entries.flatten() {
+        let path = entry.path();
+        if path.extension().is_none_or(|extension| extension != "toml") {
+            continue;
+        }
+        let file_name = entry.file_name().to_string_lossy().to_string();
+        match fs::read_to_string(&path).and_then(|text| parse_theme(&file_name, &text)) {
+            Ok(theme) => themes.push(theme),
+            Err(error) => errors.push(error),
+        }
+    }
+    themes.sort_by(|a, b| a.name.cmp(&b.name));
+    (themes, errors)
+}
+
+/// Find a theme by name. User themes take priority over built-in themes with the same name
+pub fn find_theme(name: &str) -> Option<Theme> {
+    let (user_themes, _) = load_user_themes();
+    user_themes
+        .into_iter()
+        .chain(builtin_themes())
+        .find(|theme| theme.name == name)
+}
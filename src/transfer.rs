@@ -0,0 +1,478 @@
+//! Copying and moving files and directories, used by `cp` and `mv`
+//!
+//! Every change made is recorded, so if something fails halfway, everything done so far can be undone.
+//! Files that get overwritten are first moved aside as backups, so they can be put back.
+//! Large copies show a progress bar.
+
+use std::{
+    fs,
+    io::{self, ErrorKind, Read, Write, stdout},
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
+
+use crossterm::{
+    queue,
+    terminal::{Clear, ClearType},
+};
+
+use crate::utils::format_size;
+
+/// Copies bigger than this (in bytes) show a progress bar
+const PROGRESS_THRESHOLD: u64 = 32 * 1024 * 1024;
+/// Width of the progress bar in characters
+const PROGRESS_BAR_WIDTH: usize = 30;
+
+#[derive(Default)]
+pub struct TransferOptions {
+    /// Never overwrite existing files
+    pub no_clobber: bool,
+    /// Only overwrite existing files if the source is newer
+    pub update: bool,
+    /// Keep modification times, and copy symlinks as symlinks instead of what they point to
+    pub preserve: bool,
+}
+
+/// A change made to the filesystem, that can be undone
+enum Change {
+    /// A file, symlink or directory was created
+    Created(PathBuf),
+    /// An item was moved
+    Renamed { from: PathBuf, to: PathBuf },
+    /// An existing item was moved aside, before being overwritten
+    BackedUp { backup: PathBuf, original: PathBuf },
+}
+
+pub struct Transfer {
+    options: TransferOptions,
+    changes: Vec<Change>,
+    /// Sources of moves that had to be done by copying, which are removed once the transfer is committed
+    copied_sources: Vec<PathBuf>,
+    /// Source directories whose contents were moved into existing directories. Removed once the transfer is committed, if nothing was left in them
+    merged_sources: Vec<PathBuf>,
+    total_bytes: u64,
+    copied_bytes: u64,
+    last_progress_draw: Option<Instant>,
+}
+
+impl Transfer {
+    pub fn new(options: TransferOptions) -> Self {
+        Transfer {
+            options,
+            changes: Vec::new(),
+            copied_sources: Vec::new(),
+            merged_sources: Vec::new(),
+            total_bytes: 0,
+            copied_bytes: 0,
+            last_progress_draw: None,
+        }
+    }
+
+    /// Add the size of items about to be copied to the total, so the progress bar knows how far along it is
+    pub fn add_to_total(&mut self, path: &Path) {
+        self.total_bytes += size_of(path, self.options.preserve);
+    }
+
+    /// Copy a file, symlink or directory. If the destination is an existing directory, the source directory's contents are merged into it.
+    pub fn copy(&mut self, source: &Path, dest: &Path) -> io::Result<()> {
+        // copying a directory into itself would never end
+        if source.is_dir() && is_inside(dest, source) {
+            let message = format!("Can't copy '{}' into itself", source.to_string_lossy());
+            return Err(io::Error::other(message));
+        }
+        self.copy_recursive(source, dest)
+    }
+
+    fn copy_recursive(&mut self, source: &Path, dest: &Path) -> io::Result<()> {
+        let metadata = if self.options.preserve {
+            fs::symlink_metadata(source)?
+        } else {
+            fs::metadata(source)?
+        };
+
+        if metadata.is_dir() {
+            let dest_exists = dest.is_dir();
+            if !dest_exists {
+                fs::create_dir(dest)?;
+                self.changes.push(Change::Created(dest.to_path_buf()));
+            }
+            for item in fs::read_dir(source)? {
+                let item = item?;
+                self.copy_recursive(&item.path(), &dest.join(item.file_name()))?;
+            }
+            // directories can't be opened as files on windows, so this is only best effort
+            if self.options.preserve
+                && !dest_exists
+                && let Ok(dir) = fs::File::open(dest)
+            {
+                let _ = dir.set_modified(metadata.modified()?);
+            }
+            return Ok(());
+        }
+
+        if !self.should_overwrite(source, dest)? {
+            return Ok(());
+        }
+        self.back_up(dest)?;
+
+        if metadata.is_symlink() {
+            let target = fs::read_link(source)?;
+            create_symlink(&target, dest)?;
+            self.changes.push(Change::Created(dest.to_path_buf()));
+            return Ok(());
+        }
+
+        let mut reader = fs::File::open(source)?;
+        let mut writer = fs::File::create(dest)?;
+        self.changes.push(Change::Created(dest.to_path_buf()));
+
+        let mut buf = vec![0; 256 * 1024];
+        loop {
+            let read = reader.read(&mut buf)?;
+            if read == 0 {
+                break;
+            }
+            writer.write_all(&buf[..read])?;
+            self.copied_bytes += read as u64;
+            self.draw_progress()?;
+        }
+        // like fs::copy, always keep permissions
+        fs::set_permissions(dest, metadata.permissions())?;
+        if self.options.preserve {
+            writer.set_modified(metadata.modified()?)?;
+        }
+        Ok(())
+    }
+
+    /// Move an item. Tries renaming it first, and only copies it (and then deletes the source) if that isn't possible, i.e. when moving across filesystems.
+    ///
+    /// Moving a directory onto an existing one moves its contents one by one. Items that are skipped (because of the no clobber or update options) are left in the source.
+    pub fn move_item(&mut self, source: &Path, dest: &Path) -> io::Result<()> {
+        let source_is_dir = fs::symlink_metadata(source)?.is_dir();
+
+        // moving a directory into an existing one merges them, which rename can't do
+        if source_is_dir && dest.is_dir() {
+            if is_inside(dest, source) {
+                let message = format!("Can't move '{}' into itself", source.to_string_lossy());
+                return Err(io::Error::other(message));
+            }
+            for item in fs::read_dir(source)? {
+                let item = item?;
+                self.move_item(&item.path(), &dest.join(item.file_name()))?;
+            }
+            // items moved out of it are put back on roll back, so it can only be removed when committing
+            self.merged_sources.push(source.to_path_buf());
+            return Ok(());
+        }
+
+        if !self.should_overwrite(source, dest)? {
+            return Ok(());
+        }
+        self.back_up(dest)?;
+        match fs::rename(source, dest) {
+            Ok(()) => {
+                self.changes.push(Change::Renamed {
+                    from: source.to_path_buf(),
+                    to: dest.to_path_buf(),
+                });
+                return Ok(());
+            }
+            Err(error) if error.kind() == ErrorKind::CrossesDevices => {}
+            Err(error) => return Err(error),
+        }
+
+        // nothing exists at the destination anymore, so the whole source is copied
+        self.add_to_total(source);
+        self.copy(source, dest)?;
+        self.copied_sources.push(source.to_path_buf());
+        Ok(())
+    }
+
+    /// Whether to write the source to the destination, according to the no clobber and update options
+    fn should_overwrite(&self, source: &Path, dest: &Path) -> io::Result<bool> {
+        let Ok(dest_metadata) = fs::symlink_metadata(dest) else {
+            return Ok(true);
+        };
+        if self.options.no_clobber {
+            return Ok(false);
+        }
+        if self.options.update {
+            let source_modified = fs::metadata(source)?.modified()?;
+            return Ok(source_modified > dest_metadata.modified()?);
+        }
+        Ok(true)
+    }
+
+    /// If something exists at the path, move it aside so it can be restored if the transfer fails
+    fn back_up(&mut self, path: &Path) -> io::Result<()> {
+        if fs::symlink_metadata(path).is_err() {
+            return Ok(());
+        }
+        let name = path.file_name().unwrap_or_default().to_string_lossy();
+        let mut counter = 0;
+        let backup = loop {
+            let backup = path.with_file_name(format!(".{}.shoe-backup{}", name, counter));
+            if fs::symlink_metadata(&backup).is_err() {
+                break backup;
+            }
+            counter += 1;
+        };
+        fs::rename(path, &backup)?;
+        self.changes.push(Change::BackedUp {
+            backup,
+            original: path.to_path_buf(),
+        });
+        Ok(())
+    }
+
+    fn draw_progress(&mut self) -> io::Result<()> {
+        if self.total_bytes < PROGRESS_THRESHOLD {
+            return Ok(());
+        }
+        // dont redraw too often, since it slows down the copy
+        if let Some(last) = self.last_progress_draw
+            && last.elapsed() < Duration::from_millis(100)
+            && self.copied_bytes < self.total_bytes
+        {
+            return Ok(());
+        }
+        self.last_progress_draw = Some(Instant::now());
+
+        let done = self.copied_bytes.min(self.total_bytes);
+        let filled = (done as f64 / self.total_bytes as f64 * PROGRESS_BAR_WIDTH as f64) as usize;
+        let mut stdout = stdout();
+        write!(
+            stdout,
+            "\r[{}{}] {} / {}",
+            "#".repeat(filled),
+            " ".repeat(PROGRESS_BAR_WIDTH - filled),
+            format_size(done),
+            format_size(self.total_bytes)
+        )?;
+        stdout.flush()
+    }
+
+    /// Finish the transfer, removing backups of overwritten items, the sources of moves that were done by copying,
+    /// and merged source directories that are empty
+    pub fn commit(mut self) -> io::Result<()> {
+        self.clear_progress()?;
+        // the transfer is done at this point, so keep cleaning up even if something can't be removed
+        let mut problems = Vec::new();
+        for change in self.changes.drain(..) {
+            if let Change::BackedUp { backup, .. } = change
+                && let Err(error) = remove_item(&backup)
+            {
+                problems.push(format!(
+                    "Couldn't remove the backup '{}': {}",
+                    backup.to_string_lossy(),
+                    error
+                ));
+            }
+        }
+        for source in &self.copied_sources {
+            if let Err(error) = remove_item(source) {
+                problems.push(format!(
+                    "Copied '{}', but couldn't remove the original: {}",
+                    source.to_string_lossy(),
+                    error
+                ));
+            }
+        }
+        // subdirectories come before the directories they are in, so they are removed first.
+        // directories with skipped items in them are kept, like mv does
+        for source in &self.merged_sources {
+            match fs::remove_dir(source) {
+                Ok(()) => {}
+                Err(error) if error.kind() == ErrorKind::DirectoryNotEmpty => {}
+                Err(error) => problems.push(format!(
+                    "Moved the contents of '{}', but couldn't remove it: {}",
+                    source.to_string_lossy(),
+                    error
+                )),
+            }
+        }
+        if !problems.is_empty() {
+            return Err(io::Error::other(problems.join("\n")));
+        }
+        Ok(())
+    }
+
+    /// Undo everything done so far.
+    ///
+    /// Returns an error describing what failed, and, if not everything could be undone, which paths are left over.
+    pub fn roll_back(mut self, error: io::Error) -> io::Error {
+        let _ = self.clear_progress();
+        let mut left_over = Vec::new();
+        for change in self.changes.drain(..).rev() {
+            let (result, path) = match change {
+                Change::Created(path) => (remove_item(&path), path),
+                Change::Renamed { from, to } => (fs::rename(&to, &from), to),
+                Change::BackedUp { backup, original } => (fs::rename(&backup, original), backup),
+            };
+            if result.is_err() {
+                left_over.push(path.to_string_lossy().to_string());
+            }
+        }
+        if left_over.is_empty() {
+            io::Error::other(format!("{}. No changes were made", error))
+        } else {
+            io::Error::other(format!(
+                "{}. Couldn't undo everything, these are left over: {}",
+                error,
+                left_over.join(", ")
+            ))
+        }
+    }
+
+    fn clear_progress(&self) -> io::Result<()> {
+        if self.last_progress_draw.is_some() {
+            queue!(stdout(), Clear(ClearType::CurrentLine))?;
+            write!(stdout(), "\r")?;
+            stdout().flush()?;
+        }
+        Ok(())
+    }
+}
+
+/// Remove a file, symlink or directory
+pub fn remove_item(path: &Path) -> io::Result<()> {
+    if fs::symlink_metadata(path)?.is_dir() {
+        fs::remove_dir_all(path)
+    } else {
+        fs::remove_file(path)
+    }
+}
+
+/// Whether a path is, or would be, inside a directory. The path doesn't need to exist
+fn is_inside(path: &Path, dir: &Path) -> bool {
+    let Ok(dir) = fs::canonicalize(dir) else {
+        return false;
+    };
+    // find the closest ancestor that exists, since the path itself may not
+    let path = std::path::absolute(path).unwrap_or_else(|_| path.to_path_buf());
+    for ancestor in path.ancestors() {
+        if let Ok(ancestor) = fs::canonicalize(ancestor) {
+            return ancestor.starts_with(&dir);
+        }
+    }
+    false
+}
+
+/// Total size in bytes of a file, or everything in a directory
+fn size_of(path: &Path, keep_symlinks: bool) -> u64 {
+    let metadata = if keep_symlinks {
+        fs::symlink_metadata(path)
+    } else {
+        fs::metadata(path)
+    };
+    let Ok(metadata) = metadata else {
+        return 0;
+    };
+    if !metadata.is_dir() {
+        return metadata.len();
+    }
+    let Ok(items) = fs::read_dir(path) else {
+        return 0;
+    };
+    items
+        .flatten()
+        .map(|item| size_of(&item.path(), keep_symlinks))
+        .sum()
+}
+
+#[cfg(unix)]
+fn create_symlink(target: &Path, link: &Path) -> io::Result<()> {
+    std::os::unix::fs::symlink(target, link)
+}
+
+#[cfg(windows)]
+fn create_symlink(target: &Path, link: &Path) -> io::Result<()> {
+    // windows needs to know whether the link points to a directory.
+    // relative targets are relative to the link's directory
+    let resolved = link.parent().unwrap_or(Path::new("")).join(target);
+    if resolved.is_dir() {
+        std::os::windows::fs::symlink_dir(target, link)
+    } else {
+        std::os::windows::fs::symlink_file(target, link)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!(
+            "shoe-transfer-test-{}-{}",
+            std::process::id(),
+            name
+        ));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn write(path: &Path, contents: &str) {
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, contents).unwrap();
+    }
+
+    fn read(path: &Path) -> String {
+        fs::read_to_string(path).unwrap()
+    }
+
+    #[test]
+    fn no_clobber_merge_keeps_skipped_files() {
+        let dir = temp_dir("merge");
+        let (source, dest) = (dir.join("source"), dir.join("dest"));
+        write(&source.join("conflict"), "new");
+        write(&source.join("moved"), "moved");
+        write(&source.join("sub/inner"), "inner");
+        write(&dest.join("conflict"), "old");
+        fs::create_dir(dest.join("sub")).unwrap();
+
+        let mut transfer = Transfer::new(TransferOptions {
+            no_clobber: true,
+            ..Default::default()
+        });
+        transfer.move_item(&source, &dest).unwrap();
+        transfer.commit().unwrap();
+
+        assert_eq!(read(&dest.join("conflict")), "old");
+        assert_eq!(read(&dest.join("moved")), "moved");
+        assert_eq!(read(&dest.join("sub/inner")), "inner");
+        // the skipped file is left where it was, but everything else is gone from the source
+        assert_eq!(read(&source.join("conflict")), "new");
+        assert!(!source.join("moved").exists());
+        assert!(!source.join("sub").exists());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn roll_back_undoes_everything() {
+        let dir = temp_dir("roll-back");
+        let (source, dest) = (dir.join("source"), dir.join("dest"));
+        write(&source.join("conflict"), "new");
+        write(&source.join("sub/inner"), "inner");
+        write(&dest.join("conflict"), "old");
+        write(&dir.join("file"), "file");
+
+        let mut transfer = Transfer::new(TransferOptions::default());
+        transfer.move_item(&source, &dest).unwrap();
+        transfer
+            .copy(&dir.join("file"), &dest.join("copied"))
+            .unwrap();
+        assert_eq!(read(&dest.join("conflict")), "new");
+        let error = transfer.roll_back(io::Error::other("Failed"));
+        assert_eq!(error.to_string(), "Failed. No changes were made");
+
+        assert_eq!(read(&source.join("conflict")), "new");
+        assert_eq!(read(&source.join("sub/inner")), "inner");
+        assert_eq!(read(&dest.join("conflict")), "old");
+        assert!(!dest.join("sub").exists());
+        assert!(!dest.join("copied").exists());
+        // no backups are left behind
+        assert_eq!(fs::read_dir(&dest).unwrap().count(), 1);
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
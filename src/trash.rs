@@ -0,0 +1,231 @@
+//! Moving items to the trash, and restoring them
+//!
+//! Follows the freedesktop.org trash spec, using the home trash at `$XDG_DATA_HOME/Trash` (or `~/.local/share/Trash`).
+//! Trashed items are moved to `files/`, and an `info/<name>.trashinfo` file stores where they came from and when they were deleted.
+//!
+//! On platforms without a freedesktop trash (windows and macos), the same layout is used at `~/.shoetrash`.
+
+use std::{
+    env, fs,
+    io::{self, ErrorKind},
+    path::{Path, PathBuf},
+};
+
+use crate::transfer::{Transfer, TransferOptions};
+
+/// An item in the trash
+pub struct TrashItem {
+    /// Name of the item in the trash, used to restore it
+    pub name: String,
+    /// Where the item was before it was trashed
+    pub original_path: PathBuf,
+    /// Local time of deletion, as a unix timestamp
+    pub deleted_at: Option<i64>,
+}
+
+fn trash_dir() -> PathBuf {
+    if cfg!(any(windows, target_os = "macos")) {
+        return PathBuf::from(shellexpand::tilde("~/.shoetrash").to_string());
+    }
+    match env::var_os("XDG_DATA_HOME") {
+        Some(data_home) if !data_home.is_empty() => PathBuf::from(data_home).join("Trash"),
+        _ => PathBuf::from(shellexpand::tilde("~/.local/share/Trash").to_string()),
+    }
+}
+
+fn info_path(trash: &Path, name: &str) -> PathBuf {
+    trash.join("info").join(format!("{}.trashinfo", name))
+}
+
+/// Move an item to the trash. Returns the name it got in the trash.
+pub fn move_to_trash(path: &Path) -> io::Result<String> {
+    let trash = trash_dir();
+    fs::create_dir_all(trash.join("files"))?;
+    fs::create_dir_all(trash.join("info"))?;
+
+    let original_path = std::path::absolute(path)?;
+    let file_name = original_path
+        .file_name()
+        .ok_or_else(|| io::Error::other("Can't trash a path without a name"))?
+        .to_string_lossy()
+        .to_string();
+
+    // find a free name in the trash, by adding a number to the end if needed.
+    // the info file is created with create_new, so two shoe sessions can't claim the same name
+    let mut name = file_name.clone();
+    let mut counter = 1;
+    let mut info_file = loop {
+        let exists_in_files = trash.join("files").join(&name).symlink_metadata().is_ok();
+        if !exists_in_files {
+            match fs::File::options()
+                .write(true)
+                .create_new(true)
+                .open(info_path(&trash, &name))
+            {
+                Ok(file) => break file,
+                Err(error) if error.kind() == ErrorKind::AlreadyExists => {}
+                Err(error) => return Err(error),
+            }
+        }
+        counter += 1;
+        name = format!("{}.{}", file_name, counter);
+    };
+
+    let deletion_date = chrono::Local::now().format("%Y-%m-%dT%H:%M:%S");
+    let info = format!(
+        "[Trash Info]\nPath={}\nDeletionDate={}\n",
+        percent_encode(&original_path.to_string_lossy()),
+        deletion_date
+    );
+    io::Write::write_all(&mut info_file, info.as_bytes())?;
+
+    let dest = trash.join("files").join(&name);
+    if let Err(error) = move_item(&original_path, &dest) {
+        // dont leave an info file for an item that isn't in the trash
+        let _ = fs::remove_file(info_path(&trash, &name));
+        return Err(error);
+    }
+    Ok(name)
+}
+
+/// Move an item, falling back to copying and deleting if it is on another filesystem
+fn move_item(source: &Path, dest: &Path) -> io::Result<()> {
+    let mut transfer = Transfer::new(TransferOptions {
+        preserve: true,
+        ..Default::default()
+    });
+    match transfer.move_item(source, dest) {
+        Ok(()) => transfer.commit(),
+        Err(error) => Err(transfer.roll_back(error)),
+    }
+}
+
+/// List all items in the trash, oldest first
+pub fn list_trash() -> io::Result<Vec<TrashItem>> {
+    let trash = trash_dir();
+    let Ok(entries) = fs::read_dir(trash.join("info")) else {
+        // no trash directory means nothing has been trashed yet
+        return Ok(Vec::new());
+    };
+    let mut items = Vec::new();
+    for entry in entries.flatten() {
+        let file_name = entry.file_name().to_string_lossy().to_string();
+        let Some(name) = file_name.strip_suffix(".trashinfo") else {
+            continue;
+        };
+        let Ok(info) = fs::read_to_string(entry.path()) else {
+            continue;
+        };
+        let mut original_path = None;
+        let mut deleted_at = None;
+        for line in info.lines() {
+            if let Some(path) = line.strip_prefix("Path=") {
+                original_path = Some(PathBuf::from(percent_decode(path)));
+            } else if let Some(date) = line.strip_prefix("DeletionDate=") {
+                deleted_at = chrono::NaiveDateTime::parse_from_str(date, "%Y-%m-%dT%H:%M:%S")
+                    .ok()
+                    .and_then(|date| date.and_local_timezone(chrono::Local).earliest())
+                    .map(|date| date.timestamp());
+            }
+        }
+        let Some(original_path) = original_path else {
+            continue;
+        };
+        items.push(TrashItem {
+            name: name.to_string(),
+            original_path,
+            deleted_at,
+        });
+    }
+    items.sort_by_key(|item| item.deleted_at);
+    Ok(items)
+}
+
+/// Move an item from the trash back to where it was. Returns the path it was restored to.
+pub fn restore(name: &str) -> io::Result<PathBuf> {
+    let trash = trash_dir();
+    let item = list_trash()?
+        .into_iter()
+        .find(|item| item.name == name)
+        .ok_or_else(|| io::Error::other(format!("No item by name '{}' in the trash", name)))?;
+
+    if item.original_path.symlink_metadata().is_ok() {
+        let message = format!(
+            "Can't restore, '{}' already exists",
+            item.original_path.to_string_lossy()
+        );
+        return Err(io::Error::other(message));
+    }
+    if let Some(parent) = item.original_path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    move_item(&trash.join("files").join(name), &item.original_path)?;
+    fs::remove_file(info_path(&trash, name))?;
+    Ok(item.original_path)
+}
+
+/// Permanently delete everything in the trash
+pub fn empty_trash() -> io::Result<()> {
+    let trash = trash_dir();
+    for dir in ["files", "info"] {
+        let Ok(entries) = fs::read_dir(trash.join(dir)) else {
+            continue;
+        };
+        for entry in entries.flatten() {
+            if entry.file_type()?.is_dir() {
+                fs::remove_dir_all(entry.path())?;
+            } else {
+                fs::remove_file(entry.path())?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Percent encode a path for a .trashinfo file, as the spec requires
+fn percent_encode(text: &str) -> String {
+    let mut encoded = String::new();
+    for byte in text.bytes() {
+        if byte.is_ascii_alphanumeric() || b"/-_.~".contains(&byte) {
+            encoded.push(byte as char);
+        } else {
+            encoded += &format!("%{:02X}", byte);
+        }
+    }
+    encoded
+}
+
+fn percent_decode(text: &str) -> String {
+    let bytes = text.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut index = 0;
+    while index < bytes.len() {
+        if bytes[index] == b'%'
+            && let Some(hex) = text.get(index + 1..index + 3)
+            && let Ok(byte) = u8::from_str_radix(hex, 16)
+        {
+            decoded.push(byte);
+            index += 3;
+            continue;
+        }
+        decoded.push(bytes[index]);
+        index += 1;
+    }
+    String::from_utf8_lossy(&decoded).to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn percent_encodes_paths() {
+        assert_eq!(percent_encode("/home/me/a b%.txt"), "/home/me/a%20b%25.txt");
+        assert_eq!(percent_encode("/tmp/é"), "/tmp/%C3%A9");
+        for path in ["/home/me/a b%.txt", "/tmp/é#?\n", "/plain/path_-.~"] {
+            assert_eq!(percent_decode(&percent_encode(path)), path);
+        }
+        // invalid escapes are kept as they are
+        assert_eq!(percent_decode("/a%2/b%zz%"), "/a%2/b%zz%");
+    }
+}
@@ -1,65 +1,117 @@
 //! Constants and small helper functions
 
+use std::path::PathBuf;
+
 use crossterm::style::Color;
 
 pub static DEFAULT_ERR_COLOR: Color = Color::Red;
 
 pub static HELP_MESSAGE: &str = "
---no-history  - dont store history in ~/.shoehistory
---no-rc       - dont run startup commands from ~/.shoerc
--h            - displays this help message
--c            - run all args passed afterwards as a command, then exit
--k            - run all args passed afterwards as a command";
+--no-history    - dont store history in ~/.shoehistory
+--share-history - show commands run in other open sessions in history and suggestions
+--no-rc         - dont run startup commands from ~/.shoerc
+-h              - displays this help message
+-c              - run all args passed afterwards as a command, then exit
+-k              - run all args passed afterwards as a command";
+
+/// Directory of shoe's config file and user themes, ~/.config/shoe
+pub fn config_dir() -> PathBuf {
+    PathBuf::from(shellexpand::tilde("~/.config/shoe").to_string())
+}
+
+/// Format a duration in milliseconds to a short human readable string, like `350ms`, `4.2s` or `3m 12s`
+pub fn format_duration(millis: u64) -> String {
+    if millis < 1000 {
+        format!("{}ms", millis)
+    } else if millis < 60_000 {
+        format!("{:.1}s", millis as f64 / 1000.0)
+    } else if millis < 3_600_000 {
+        format!("{}m {}s", millis / 60_000, (millis / 1000) % 60)
+    } else {
+        format!("{}h {}m", millis / 3_600_000, (millis / 60_000) % 60)
+    }
+}
+
+/// Format a unix timestamp (in seconds) as local time, like `2025-06-01 14:32`
+pub fn format_timestamp(timestamp: i64) -> String {
+    chrono::DateTime::from_timestamp(timestamp, 0)
+        .map(|time| {
+            time.with_timezone(&chrono::Local)
+                .format("%Y-%m-%d %H:%M")
+                .to_string()
+        })
+        .unwrap_or_default()
+}
+
+/// Format a size in bytes to a short human readable string, like `512B`, `4.2K` or `31M`
+pub fn format_size(bytes: u64) -> String {
+    const UNITS: &[&str] = &["K", "M", "G", "T"];
+    if bytes < 1024 {
+        return format!("{}B", bytes);
+    }
+    let mut size = bytes as f64 / 1024.0;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    // only show decimals for small numbers, like `ls -lh` does
+    if size < 10.0 {
+        format!("{:.1}{}", size, UNITS[unit])
+    } else {
+        format!("{:.0}{}", size, UNITS[unit])
+    }
+}
+
+/// Match text against a pattern with wildcards, where `*` matches any amount of characters and `?` matches exactly one
+pub fn wildcard_match(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+
+    let mut pattern_index = 0;
+    let mut text_index = 0;
+    // position of the last `*` in the pattern, and the text position it was tried at, to backtrack to
+    let mut last_star: Option<(usize, usize)> = None;
 
-pub struct Theme {
-    pub name: &'static str,
-    pub primary_color: Color,
-    pub secondary_color: Color,
-    pub err_color: Color,
+    while text_index < text.len() {
+        match pattern.get(pattern_index) {
+            Some('*') => {
+                last_star = Some((pattern_index, text_index));
+                pattern_index += 1;
+            }
+            Some(c) if *c == '?' || *c == text[text_index] => {
+                pattern_index += 1;
+                text_index += 1;
+            }
+            _ => {
+                // mismatch, let the last star swallow one more character
+                let Some((star_index, star_text_index)) = last_star else {
+                    return false;
+                };
+                pattern_index = star_index + 1;
+                text_index = star_text_index + 1;
+                last_star = Some((star_index, text_index));
+            }
+        }
+    }
+    // any remaining pattern must be only stars
+    pattern[pattern_index..].iter().all(|c| *c == '*')
 }
-const fn hex_to_color(hex: u32) -> Color {
+
+pub const fn hex_to_color(hex: u32) -> Color {
     Color::Rgb {
         r: ((hex >> 16) & 0xFF) as u8,
         g: ((hex >> 8) & 0xFF) as u8,
         b: ((hex) & 0xFF) as u8,
     }
 }
-pub static THEMES: &[Theme] = &[
-    Theme {
-        name: "gold",
-        primary_color: hex_to_color(0xFFC145),
-        secondary_color: hex_to_color(0x5B5F97),
-        err_color: DEFAULT_ERR_COLOR,
-    },
-    Theme {
-        name: "earth",
-        primary_color: hex_to_color(0x45FF8C),
-        secondary_color: hex_to_color(0x97645B),
-        err_color: DEFAULT_ERR_COLOR,
-    },
-    Theme {
-        name: "element",
-        primary_color: hex_to_color(0xFF4C4F),
-        secondary_color: hex_to_color(0x89B4E5),
-        err_color: DEFAULT_ERR_COLOR,
-    },
-    Theme {
-        name: "lime",
-        primary_color: hex_to_color(0x9DE64E),
-        secondary_color: hex_to_color(0x72A6FF),
-        err_color: DEFAULT_ERR_COLOR,
-    },
-    Theme {
-        name: "fire",
-        primary_color: hex_to_color(0xFF2B32),
-        secondary_color: hex_to_color(0xFF6E00),
-        err_color: DEFAULT_ERR_COLOR,
-    },
-];
-
-pub static DEBUG_THEME: Theme = Theme {
-    name: "debug",
-    primary_color: hex_to_color(0xb2deff),
-    secondary_color: hex_to_color(0xffd68f),
-    err_color: DEFAULT_ERR_COLOR,
-};
+/// Parse a color from a name (like `red` or `dark_grey`) or a hex code (like `#ff8800`)
+pub fn parse_color(text: &str) -> Option<Color> {
+    if let Some(hex) = text.strip_prefix('#') {
+        if hex.len() != 6 {
+            return None;
+        }
+        return u32::from_str_radix(hex, 16).ok().map(hex_to_color);
+    }
+    Color::try_from(text).ok()
+}
@@ -0,0 +1,603 @@
+//! Pager for viewing files with syntax highlighting, used by the `view` builtin
+//!
+//! The file is shown in the terminal's alternate screen, so the shell's output is left untouched once the pager is closed.
+//! Highlighting is done line by line, with a bit of state carried between lines (for block comments and markdown code blocks).
+//! Colors come from the active theme.
+
+use std::io::{self, Write, stdout};
+
+use crossterm::{
+    cursor, event,
+    event::{Event, KeyCode, KeyEventKind, KeyModifiers},
+    queue,
+    style::{Attribute, Color, SetAttribute, SetForegroundColor},
+    terminal::{self, Clear, ClearType, EnterAlternateScreen, LeaveAlternateScreen},
+};
+
+use crate::theme::Theme;
+
+/// Tabs are shown as this many spaces
+const TAB_WIDTH: usize = 4;
+/// Color of comments, and other text that should be toned down
+const COMMENT_COLOR: Color = Color::DarkGrey;
+
+#[derive(Clone, Copy, PartialEq)]
+enum Language {
+    Rust,
+    Json,
+    Toml,
+    Markdown,
+    Shell,
+    Plain,
+}
+
+impl Language {
+    /// Guess the language of a file from its name
+    fn from_file_name(name: &str) -> Self {
+        let file_name = name.rsplit(['/', '\\']).next().unwrap_or(name);
+        if matches!(file_name, ".bashrc" | ".zshrc" | ".profile" | ".shoerc") {
+            return Language::Shell;
+        }
+        let Some((_, extension)) = file_name.rsplit_once('.') else {
+            return Language::Plain;
+        };
+        match extension.to_lowercase().as_str() {
+            "rs" => Language::Rust,
+            "json" => Language::Json,
+            "toml" => Language::Toml,
+            "md" | "markdown" => Language::Markdown,
+            "sh" | "bash" | "zsh" => Language::Shell,
+            _ => Language::Plain,
+        }
+    }
+}
+
+const RUST_KEYWORDS: &[&str] = &[
+    "as", "async", "await", "break", "const", "continue", "crate", "dyn", "else", "enum", "extern",
+    "false", "fn", "for", "if", "impl", "in", "let", "loop", "match", "mod", "move", "mut", "pub",
+    "ref", "return", "self", "Self", "static", "struct", "super", "trait", "true", "type",
+    "unsafe", "use", "where", "while",
+];
+const SHELL_KEYWORDS: &[&str] = &[
+    "if", "then", "else", "elif", "fi", "for", "while", "until", "do", "done", "case", "esac",
+    "in", "function", "return", "export", "local",
+];
+const JSON_KEYWORDS: &[&str] = &["true", "false", "null"];
+const TOML_KEYWORDS: &[&str] = &["true", "false"];
+
+/// Colors a file line by line
+struct Highlighter {
+    language: Language,
+    keyword_color: Color,
+    string_color: Color,
+    /// Whether the previous line ended inside a block comment
+    in_block_comment: bool,
+    /// Whether the previous line was inside a markdown code block
+    in_code_block: bool,
+}
+
+impl Highlighter {
+    fn new(language: Language, theme: &Theme) -> Self {
+        Highlighter {
+            language,
+            keyword_color: theme.primary_color,
+            string_color: theme.secondary_color,
+            in_block_comment: false,
+            in_code_block: false,
+        }
+    }
+
+    /// Get the color of every char in a line
+    fn highlight(&mut self, line: &str) -> Vec<Option<Color>> {
+        let chars: Vec<char> = line.chars().collect();
+        match self.language {
+            Language::Plain => vec![None; chars.len()],
+            Language::Markdown => self.highlight_markdown(&chars),
+            _ => self.highlight_code(&chars),
+        }
+    }
+
+    fn highlight_markdown(&mut self, chars: &[char]) -> Vec<Option<Color>> {
+        let mut colors = vec![None; chars.len()];
+        let text: String = chars.iter().collect();
+        let trimmed = text.trim_start();
+        let indent = chars.len() - trimmed.chars().count();
+
+        if trimmed.starts_with("```") {
+            self.in_code_block = !self.in_code_block;
+            colors.fill(Some(COMMENT_COLOR));
+            return colors;
+        }
+        if self.in_code_block {
+            colors.fill(Some(self.string_color));
+            return colors;
+        }
+        if trimmed.starts_with('#') {
+            colors.fill(Some(self.keyword_color));
+            return colors;
+        }
+        if trimmed.starts_with('>') {
+            colors.fill(Some(COMMENT_COLOR));
+            return colors;
+        }
+
+        // list markers, like "- ", "* " or "1. "
+        let digits = trimmed.chars().take_while(char::is_ascii_digit).count();
+        let marker_len = if ["- ", "* ", "+ "].iter().any(|m| trimmed.starts_with(m)) {
+            1
+        } else if digits > 0 && trimmed[digits..].starts_with(". ") {
+            digits + 1
+        } else {
+            0
+        };
+        colors[indent..indent + marker_len].fill(Some(self.keyword_color));
+
+        // inline code
+        let mut index = indent + marker_len;
+        while index < chars.len() {
+            if chars[index] == '`'
+                && let Some(end) = find_char(chars, '`', index + 1)
+            {
+                colors[index..=end].fill(Some(self.string_color));
+                index = end + 1;
+                continue;
+            }
+            index += 1;
+        }
+        colors
+    }
+
+    fn highlight_code(&mut self, chars: &[char]) -> Vec<Option<Color>> {
+        let mut colors = vec![None; chars.len()];
+        let language = self.language;
+        let (line_comment, keywords, quotes): (&[char], &[&str], &[char]) = match language {
+            Language::Rust => (&['/', '/'], RUST_KEYWORDS, &['"']),
+            Language::Shell => (&['#'], SHELL_KEYWORDS, &['"', '\'']),
+            Language::Toml => (&['#'], TOML_KEYWORDS, &['"', '\'']),
+            _ => (&[], JSON_KEYWORDS, &['"']),
+        };
+
+        let mut index = 0;
+        if self.in_block_comment {
+            match find_sequence(chars, &['*', '/'], 0) {
+                Some(end) => {
+                    colors[..end + 2].fill(Some(COMMENT_COLOR));
+                    index = end + 2;
+                    self.in_block_comment = false;
+                }
+                None => {
+                    colors.fill(Some(COMMENT_COLOR));
+                    return colors;
+                }
+            }
+        }
+
+        if language == Language::Toml {
+            let first = chars.iter().position(|c| !c.is_whitespace());
+            if first.is_some_and(|first| chars[first] == '[') {
+                // [section] headers
+                colors.fill(Some(self.keyword_color));
+                return colors;
+            }
+            // keys before the =
+            if let Some(equals) = find_char(chars, '=', 0)
+                && !chars[..equals]
+                    .iter()
+                    .any(|c| quotes.contains(c) || *c == '#')
+            {
+                colors[..equals].fill(Some(self.keyword_color));
+                index = equals + 1;
+            }
+        }
+
+        while index < chars.len() {
+            let char = chars[index];
+            let previous = index.checked_sub(1).map(|i| chars[i]);
+
+            // in shell and toml, # only starts a comment at the start of a word
+            let comment_allowed = language == Language::Rust
+                || previous.is_none_or(|previous| previous.is_whitespace());
+            if !line_comment.is_empty()
+                && comment_allowed
+                && chars[index..].starts_with(line_comment)
+            {
+                colors[index..].fill(Some(COMMENT_COLOR));
+                break;
+            }
+            if language == Language::Rust && chars[index..].starts_with(&['/', '*']) {
+                match find_sequence(chars, &['*', '/'], index + 2) {
+                    Some(end) => {
+                        colors[index..end + 2].fill(Some(COMMENT_COLOR));
+                        index = end + 2;
+                        continue;
+                    }
+                    None => {
+                        colors[index..].fill(Some(COMMENT_COLOR));
+                        self.in_block_comment = true;
+                        break;
+                    }
+                }
+            }
+            if quotes.contains(&char) {
+                let end = find_string_end(chars, index).unwrap_or(chars.len() - 1);
+                // json keys are strings followed by a :
+                let is_key = language == Language::Json
+                    && chars[end + 1..].iter().find(|c| !c.is_whitespace()) == Some(&':');
+                let color = if is_key {
+                    self.keyword_color
+                } else {
+                    self.string_color
+                };
+                colors[index..=end].fill(Some(color));
+                index = end + 1;
+                continue;
+            }
+            if language == Language::Shell && char == '$' {
+                let end = if chars.get(index + 1) == Some(&'{') {
+                    find_char(chars, '}', index).unwrap_or(chars.len() - 1) + 1
+                } else {
+                    index + 1 + word_len(&chars[index + 1..])
+                };
+                colors[index..end].fill(Some(self.string_color));
+                index = end;
+                continue;
+            }
+            if is_word_char(char) {
+                let len = word_len(&chars[index..]);
+                let word: String = chars[index..index + len].iter().collect();
+                if char.is_ascii_digit() {
+                    colors[index..index + len].fill(Some(self.string_color));
+                } else if keywords.contains(&word.as_str()) {
+                    colors[index..index + len].fill(Some(self.keyword_color));
+                }
+                index += len;
+                continue;
+            }
+            index += 1;
+        }
+        colors
+    }
+}
+
+fn is_word_char(char: char) -> bool {
+    char.is_alphanumeric() || char == '_'
+}
+
+/// Amount of word chars at the start of the slice
+fn word_len(chars: &[char]) -> usize {
+    chars.iter().take_while(|c| is_word_char(**c)).count()
+}
+
+fn find_char(chars: &[char], target: char, start: usize) -> Option<usize> {
+    chars
+        .iter()
+        .skip(start)
+        .position(|c| *c == target)
+        .map(|pos| pos + start)
+}
+
+fn find_sequence(chars: &[char], sequence: &[char], start: usize) -> Option<usize> {
+    (start..chars.len()).find(|index| chars[*index..].starts_with(sequence))
+}
+
+/// Find the closing quote of a string starting at `start`. Backslashes escape the next char, except in single quoted strings
+fn find_string_end(chars: &[char], start: usize) -> Option<usize> {
+    let quote = chars[start];
+    let mut index = start + 1;
+    while index < chars.len() {
+        if chars[index] == '\\' && quote != '\'' {
+            index += 2;
+            continue;
+        }
+        if chars[index] == quote {
+            return Some(index);
+        }
+        index += 1;
+    }
+    None
+}
+
+/// Text input at the bottom of the pager
+enum Prompt {
+    Search(String),
+    Line(String),
+}
+
+struct Pager<'a> {
+    name: &'a str,
+    /// Every line of the file, as chars with their colors. Tabs are already expanded
+    lines: Vec<Vec<(char, Option<Color>)>>,
+    /// Index of the first line shown
+    top: usize,
+    /// Amount of columns scrolled to the right
+    left: usize,
+    search: Option<String>,
+    prompt: Option<Prompt>,
+    /// Message shown in the status line, until the next key press
+    message: Option<String>,
+    theme: &'a Theme,
+}
+
+impl<'a> Pager<'a> {
+    fn new(name: &'a str, text: &str, theme: &'a Theme) -> Self {
+        let mut highlighter = Highlighter::new(Language::from_file_name(name), theme);
+        let lines = text
+            .lines()
+            .map(|line| {
+                let colors = highlighter.highlight(line);
+                let mut cells = Vec::with_capacity(line.len());
+                for (char, color) in line.chars().zip(colors) {
+                    if char == '\t' {
+                        cells.extend(std::iter::repeat_n((' ', color), TAB_WIDTH));
+                    } else if !char.is_control() {
+                        cells.push((char, color));
+                    }
+                }
+                cells
+            })
+            .collect();
+        Pager {
+            name,
+            lines,
+            top: 0,
+            left: 0,
+            search: None,
+            prompt: None,
+            message: None,
+            theme,
+        }
+    }
+
+    /// Amount of lines that fit on screen, excluding the status line
+    fn page_height() -> io::Result<usize> {
+        let (_, height) = terminal::size()?;
+        Ok((height as usize).saturating_sub(1).max(1))
+    }
+
+    fn max_top(&self) -> io::Result<usize> {
+        Ok(self.lines.len().saturating_sub(Self::page_height()?))
+    }
+
+    fn scroll_to(&mut self, top: usize) -> io::Result<()> {
+        self.top = top.min(self.max_top()?);
+        Ok(())
+    }
+
+    fn scroll_by(&mut self, amount: isize) -> io::Result<()> {
+        self.scroll_to(self.top.saturating_add_signed(amount))
+    }
+
+    /// Scroll to the next line (or previous, if `backwards`) containing the search text, starting at `from`
+    fn find(&mut self, from: usize, backwards: bool) -> io::Result<()> {
+        let Some(search) = &self.search else {
+            return Ok(());
+        };
+        let query: Vec<char> = search.chars().collect();
+        let ignore_case = !search.chars().any(char::is_uppercase);
+        let line_matches = |line: &Vec<(char, Option<Color>)>| {
+            !match_positions(line, &query, ignore_case).is_empty()
+        };
+
+        let found = if backwards {
+            (0..from.min(self.lines.len()))
+                .rev()
+                .find(|index| line_matches(&self.lines[*index]))
+        } else {
+            (from..self.lines.len()).find(|index| line_matches(&self.lines[*index]))
+        };
+        match found {
+            Some(index) => self.scroll_to(index)?,
+            None => self.message = Some(format!("Pattern not found: {}", search)),
+        }
+        Ok(())
+    }
+
+    fn draw(&self) -> io::Result<()> {
+        let mut stdout = stdout();
+        let (width, _) = terminal::size()?;
+        let width = width as usize;
+        let height = Self::page_height()?;
+        let gutter_width = self.lines.len().max(1).to_string().len();
+        let text_width = width.saturating_sub(gutter_width + 1);
+
+        let query: Option<Vec<char>> = self.search.as_ref().map(|s| s.chars().collect());
+        let ignore_case = self
+            .search
+            .as_ref()
+            .is_some_and(|s| !s.chars().any(char::is_uppercase));
+
+        for row in 0..height {
+            queue!(
+                stdout,
+                cursor::MoveTo(0, row as u16),
+                Clear(ClearType::CurrentLine)
+            )?;
+            let index = self.top + row;
+            let Some(line) = self.lines.get(index) else {
+                queue!(stdout, SetForegroundColor(COMMENT_COLOR))?;
+                write!(stdout, "~")?;
+                continue;
+            };
+            queue!(stdout, SetForegroundColor(COMMENT_COLOR))?;
+            write!(stdout, "{:>gutter_width$} ", index + 1)?;
+
+            let matches = match &query {
+                Some(query) => match_positions(line, query, ignore_case),
+                None => Vec::new(),
+            };
+            let mut current_color = None;
+            let mut reversed = false;
+            for (position, (char, color)) in
+                line.iter().enumerate().skip(self.left).take(text_width)
+            {
+                if current_color != Some(*color) {
+                    queue!(stdout, SetForegroundColor(color.unwrap_or(Color::Reset)))?;
+                    current_color = Some(*color);
+                }
+                let highlighted = matches.contains(&position);
+                if highlighted != reversed {
+                    let attribute = if highlighted {
+                        Attribute::Reverse
+                    } else {
+                        Attribute::NoReverse
+                    };
+                    queue!(stdout, SetAttribute(attribute))?;
+                    reversed = highlighted;
+                }
+                write!(stdout, "{}", char)?;
+            }
+            if reversed {
+                queue!(stdout, SetAttribute(Attribute::NoReverse))?;
+            }
+        }
+
+        // status line
+        let status = match &self.prompt {
+            Some(Prompt::Search(text)) => format!("/{}", text),
+            Some(Prompt::Line(text)) => format!(":{}", text),
+            None => match &self.message {
+                Some(message) => message.clone(),
+                None => {
+                    let last = (self.top + height).min(self.lines.len());
+                    format!(
+                        "{}  lines {}-{} of {}  (q: quit, /: search, n/N: next/previous match, :: go to line)",
+                        self.name,
+                        (self.top + 1).min(last),
+                        last,
+                        self.lines.len()
+                    )
+                }
+            },
+        };
+        let status: String = status.chars().take(width).collect();
+        queue!(
+            stdout,
+            cursor::MoveTo(0, height as u16),
+            Clear(ClearType::CurrentLine),
+            SetForegroundColor(self.theme.primary_color),
+            SetAttribute(Attribute::Reverse)
+        )?;
+        write!(stdout, "{:<width$}", status)?;
+        queue!(
+            stdout,
+            SetAttribute(Attribute::NoReverse),
+            SetForegroundColor(Color::Reset)
+        )?;
+        stdout.flush()
+    }
+
+    /// Handle a key press. Returns false when the pager should close
+    fn handle_key(&mut self, code: KeyCode, modifiers: KeyModifiers) -> io::Result<bool> {
+        self.message = None;
+        if let Some(prompt) = &mut self.prompt {
+            let text = match prompt {
+                Prompt::Search(text) | Prompt::Line(text) => text,
+            };
+            match code {
+                KeyCode::Char(char) => text.push(char),
+                KeyCode::Backspace => {
+                    text.pop();
+                }
+                KeyCode::Esc => self.prompt = None,
+                KeyCode::Enter => match self.prompt.take() {
+                    Some(Prompt::Search(text)) if !text.is_empty() => {
+                        self.search = Some(text);
+                        self.find(self.top, false)?;
+                    }
+                    Some(Prompt::Line(text)) => match text.trim().parse::<usize>() {
+                        Ok(line) => self.scroll_to(line.saturating_sub(1))?,
+                        Err(_) => self.message = Some(format!("Invalid line number: {}", text)),
+                    },
+                    _ => {}
+                },
+                _ => {}
+            }
+            return Ok(true);
+        }
+
+        let page = Self::page_height()? as isize;
+        match code {
+            KeyCode::Char('q') | KeyCode::Esc => return Ok(false),
+            KeyCode::Char('c') if modifiers.contains(KeyModifiers::CONTROL) => return Ok(false),
+            KeyCode::Down | KeyCode::Char('j') | KeyCode::Enter => self.scroll_by(1)?,
+            KeyCode::Up | KeyCode::Char('k') => self.scroll_by(-1)?,
+            KeyCode::PageDown | KeyCode::Char(' ') | KeyCode::Char('f') => self.scroll_by(page)?,
+            KeyCode::PageUp | KeyCode::Char('b') => self.scroll_by(-page)?,
+            KeyCode::Home | KeyCode::Char('g') => self.scroll_to(0)?,
+            KeyCode::End | KeyCode::Char('G') => self.scroll_to(usize::MAX)?,
+            KeyCode::Right | KeyCode::Char('l') => self.left += TAB_WIDTH,
+            KeyCode::Left | KeyCode::Char('h') => self.left = self.left.saturating_sub(TAB_WIDTH),
+            KeyCode::Char('/') => self.prompt = Some(Prompt::Search(String::new())),
+            KeyCode::Char(':') => self.prompt = Some(Prompt::Line(String::new())),
+            KeyCode::Char('n') => self.find(self.top + 1, false)?,
+            KeyCode::Char('N') => self.find(self.top, true)?,
+            _ => {}
+        }
+        Ok(true)
+    }
+
+    fn run(&mut self) -> io::Result<()> {
+        loop {
+            self.draw()?;
+            match event::read()? {
+                Event::Key(key) if key.kind == KeyEventKind::Press => {
+                    let keep_open = self.handle_key(key.code, key.modifiers)?;
+                    if !keep_open {
+                        return Ok(());
+                    }
+                }
+                Event::Resize(_, _) => self.scroll_by(0)?,
+                _ => {}
+            }
+        }
+    }
+}
+
+/// Char positions in a line that are part of a match of the query
+fn match_positions(
+    line: &[(char, Option<Color>)],
+    query: &[char],
+    ignore_case: bool,
+) -> Vec<usize> {
+    let mut positions = Vec::new();
+    if query.is_empty() || query.len() > line.len() {
+        return positions;
+    }
+    let equal = |a: char, b: char| {
+        if ignore_case {
+            a.to_lowercase().eq(b.to_lowercase())
+        } else {
+            a == b
+        }
+    };
+    for start in 0..=line.len() - query.len() {
+        let found = query
+            .iter()
+            .enumerate()
+            .all(|(offset, char)| equal(line[start + offset].0, *char));
+        if found {
+            positions.extend(start..start + query.len());
+        }
+    }
+    positions
+}
+
+/// Show text in the pager, until the user closes it. The name is shown in the status line, and decides the highlighting
+pub fn view(name: &str, text: &str, theme: &Theme) -> io::Result<()> {
+    let mut pager = Pager::new(name, text, theme);
+
+    terminal::enable_raw_mode()?;
+    queue!(stdout(), EnterAlternateScreen, cursor::Hide)?;
+    let result = pager.run();
+
+    // always leave the alternate screen, even if something went wrong
+    queue!(
+        stdout(),
+        SetForegroundColor(Color::Reset),
+        cursor::Show,
+        LeaveAlternateScreen
+    )?;
+    stdout().flush()?;
+    terminal::disable_raw_mode()?;
+    result
+}